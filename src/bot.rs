@@ -0,0 +1,376 @@
+//! The bot listens on the Discord gateway for commands, so the monitored subreddits can be managed
+//! from Discord rather than by editing the config file and restarting. Every change is saved to the
+//! config right away.
+//!
+//! Only the guild owner and the config's `admins` can change what everyone gets:
+//!     !watch <subreddit> <query>    Starts (or updates) a search of a subreddit
+//!     !unwatch <subreddit>          Stops searching a subreddit
+//!     !pause [subreddit]            Pauses one subreddit, or everything
+//!     !resume [subreddit]           Resumes one subreddit, or everything
+//!     !interval <duration>          Changes how long to wait between checks (e.g. 600s)
+//!
+//! Everyone can list the searches, and keep searches of their own, whose results are only sent to them:
+//!     !list                         Lists the searches
+//!     !subscribe <subreddit> <query>
+//!     !unsubscribe <subreddit>
+//!     !subscriptions
+//!
+//! Reactions on the notifications are handled here too; see the `feedback` module.
+//!     !favorites                    Lists the posts you've starred
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use serenity::http;
+use serenity::model::channel::{Message, Reaction, ReactionType};
+use serenity::prelude::{Context, EventHandler};
+
+use crate::config::{Config, Query, SubredditData};
+use crate::feedback::Favorite;
+use crate::state::State;
+use crate::utils::{is_subreddit_name, parse_duration};
+
+/// The shortest interval between checks that `!interval` accepts, to stay well within Reddit's rate limits.
+const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct CommandError(pub String);
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Error for CommandError {}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Watch { subreddit: String, query: String },
+    Unwatch(String),
+    List,
+    Pause(Option<String>),
+    Resume(Option<String>),
+    Interval(Duration),
+    Subscribe { subreddit: String, query: String },
+    Unsubscribe(String),
+    Subscriptions,
+    Favorites,
+}
+impl Command {
+    /// Reads a command out of a message, returning `None` for messages that aren't commands at all.
+    pub fn parse(s: &str) -> Option<Result<Command, CommandError>> {
+        let s = s.trim();
+        if !s.starts_with('!') {
+            return None;
+        }
+        let mut words = s[1..].split_whitespace();
+        let name = words.next()?.to_lowercase();
+        let sub = words.next().map(|w| w.trim_start_matches("r/").to_string());
+        let rest = words.collect::<Vec<&str>>().join(" ");
+        let rest = if rest.is_empty() { None } else { Some(rest) };
+
+        Some(match (name.as_str(), sub, rest) {
+            ("watch", Some(subreddit), Some(query)) => _subreddit(subreddit).map(|subreddit| Command::Watch { subreddit, query }),
+            ("watch", _, _) => Err(CommandError("Usage: !watch <subreddit> <query>".to_string())),
+            ("unwatch", Some(subreddit), None) => Ok(Command::Unwatch(subreddit)),
+            ("unwatch", _, _) => Err(CommandError("Usage: !unwatch <subreddit>".to_string())),
+            ("list", None, None) => Ok(Command::List),
+            ("pause", sub, None) => Ok(Command::Pause(sub)),
+            ("resume", sub, None) => Ok(Command::Resume(sub)),
+            ("interval", Some(d), None) => parse_duration(&d)
+                .map(Command::Interval)
+                .map_err(|e| CommandError(format!("Couldn't read the interval: {}", e))),
+            ("interval", _, _) => Err(CommandError("Usage: !interval <duration>, e.g. !interval 600s".to_string())),
+            ("subscribe", Some(subreddit), Some(query)) => _subreddit(subreddit).map(|subreddit| Command::Subscribe { subreddit, query }),
+            ("subscribe", _, _) => Err(CommandError("Usage: !subscribe <subreddit> <query>".to_string())),
+            ("unsubscribe", Some(subreddit), None) => Ok(Command::Unsubscribe(subreddit)),
+            ("unsubscribe", _, _) => Err(CommandError("Usage: !unsubscribe <subreddit>".to_string())),
+            ("subscriptions", None, None) => Ok(Command::Subscriptions),
+            ("favorites", None, None) => Ok(Command::Favorites),
+            _ => return None,
+        })
+    }
+    /// Whether the command changes what everyone gets, rather than only what the user does.
+    pub fn is_global(&self) -> bool {
+        match self {
+            Command::Watch { .. } | Command::Unwatch(_) | Command::Pause(_) | Command::Resume(_) | Command::Interval(_) => true,
+            _ => false,
+        }
+    }
+    /// Applies the command to the shared config on behalf of a user and saves it, returning a reply.
+    pub fn run(self, config: &Mutex<Config>, user: u64) -> Result<String, CommandError> {
+        let mut config = config.lock().expect("Arc lock was poisoned in the config");
+        let reply = self.apply(&mut config, user)?;
+        config.write(&config.path).map_err(|e| CommandError(format!("The change was made, but couldn't be saved: {}", e)))?;
+        Ok(reply)
+    }
+    /// Applies the command to the config on behalf of a user, returning a reply for them.
+    pub fn apply(self, config: &mut Config, user: u64) -> Result<String, CommandError> {
+        match self {
+            Command::Watch { subreddit, query } => {
+                let reply = format!("Now watching r/{} for \"{}\".", subreddit, query);
+                _watch(&mut config.subreddit_configs, subreddit, query);
+                Ok(reply)
+            },
+            Command::Unwatch(subreddit) => _unwatch(&mut config.subreddit_configs, &subreddit)
+                .map(|_| format!("Stopped watching r/{}.", subreddit)),
+            Command::List => Ok(_list(config)),
+            Command::Pause(sub) => _set_paused(config, sub, true),
+            Command::Resume(sub) => _set_paused(config, sub, false),
+            Command::Interval(d) => {
+                if d < MIN_INTERVAL {
+                    return Err(CommandError(format!("The interval has to be at least {}.", humantime::format_duration(MIN_INTERVAL))));
+                }
+                config.interval = Some(d.as_secs());
+                Ok(format!("Now checking every {}.", humantime::format_duration(Duration::from_secs(d.as_secs()))))
+            },
+            Command::Subscribe { subreddit, query } => {
+                let reply = format!("You're now subscribed to r/{} for \"{}\".", subreddit, query);
+                _watch(config.subscriptions.entry(user).or_insert_with(Vec::new), subreddit, query);
+                Ok(reply)
+            },
+            Command::Unsubscribe(subreddit) => {
+                let subs = config.subscriptions.entry(user).or_insert_with(Vec::new);
+                let result = _unwatch(subs, &subreddit);
+                if subs.is_empty() {
+                    config.subscriptions.remove(&user);
+                }
+                result.map(|_| format!("You're no longer subscribed to r/{}.", subreddit))
+            },
+            Command::Subscriptions => Ok(match config.subscriptions.get(&user) {
+                Some(subs) => _describe(subs),
+                None => "You aren't subscribed to anything.".to_string(),
+            }),
+            // favorites live in the state rather than the config, so the handler answers this one
+            Command::Favorites => Err(CommandError("Favorites aren't kept in the config.".to_string())),
+        }
+    }
+}
+
+/// Turns away subreddit names that can't be one, so they never get searched.
+fn _subreddit(name: String) -> Result<String, CommandError> {
+    if is_subreddit_name(&name) {
+        Ok(name)
+    } else {
+        Err(CommandError(format!("\"{}\" isn't a subreddit name.", name)))
+    }
+}
+
+/// Starts searching a subreddit, or changes the query if it's already being searched.
+fn _watch(subs: &mut Vec<SubredditData>, subreddit: String, query: String) {
+    match subs.iter_mut().find(|c| c.subreddit == subreddit) {
+        Some(existing) => existing.search_query.q = query,
+        None => subs.push(SubredditData {
+            subreddit,
+            search_query: Query { q: query, ..Query::default() },
+            templates: Default::default(),
+            paused: false,
+            notify: None,
+            digest: Default::default(),
+            priorities: Vec::new(),
+        }),
+    }
+}
+
+fn _unwatch(subs: &mut Vec<SubredditData>, subreddit: &str) -> Result<(), CommandError> {
+    let before = subs.len();
+    subs.retain(|c| c.subreddit != subreddit);
+    if subs.len() == before {
+        Err(CommandError(format!("r/{} isn't being watched.", subreddit)))
+    } else {
+        Ok(())
+    }
+}
+
+fn _describe(subs: &[SubredditData]) -> String {
+    subs.iter()
+        .map(|c| format!("r/{}: \"{}\"{}", c.subreddit, c.search_query.q, if c.paused { " (paused)" } else { "" }))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn _list(config: &Config) -> String {
+    let mut reply = if config.subreddit_configs.is_empty() {
+        "Nothing is being watched.".to_string()
+    } else {
+        _describe(&config.subreddit_configs)
+    };
+    if config.paused {
+        reply.push_str("\nEverything is paused.");
+    }
+    reply
+}
+
+fn _favorites(favorites: &[Favorite]) -> String {
+    if favorites.is_empty() {
+        return "You haven't starred any posts yet.".to_string();
+    }
+    favorites.iter()
+        .map(|f| format!("{} {}", f.subreddit, f.permalink()))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn _set_paused(config: &mut Config, sub: Option<String>, paused: bool) -> Result<String, CommandError> {
+    let verb = if paused { "Paused" } else { "Resumed" };
+    match sub {
+        None => {
+            config.paused = paused;
+            Ok(format!("{} everything.", verb))
+        },
+        Some(sub) => match config.subreddit_configs.iter_mut().find(|c| c.subreddit == sub) {
+            Some(c) => {
+                c.paused = paused;
+                Ok(format!("{} r/{}.", verb, sub))
+            },
+            None => Err(CommandError(format!("r/{} isn't being watched.", sub))),
+        },
+    }
+}
+
+/// Handles gateway events for the bot, sharing its config and state with the `Monitor`.
+pub struct Handler {
+    config: Arc<Mutex<Config>>,
+    state: Arc<Mutex<State>>,
+}
+impl Handler {
+    pub fn new(config: Arc<Mutex<Config>>, state: Arc<Mutex<State>>) -> Handler {
+        Handler { config, state }
+    }
+    /// Whether the user is one of the config's admins or owns the bot's guild.
+    fn _is_admin(&self, user: u64) -> bool {
+        if self.config.lock().expect("Arc lock was poisoned in the config").admins.contains(&user) {
+            return true;
+        }
+        match _guild_owner() {
+            Ok(owner) => owner == user,
+            Err(e) => {
+                eprintln!("Could not look up the guild's owner: {:?}", e);
+                false
+            },
+        }
+    }
+}
+
+/// The owner of the (first) guild the bot is in.
+fn _guild_owner() -> serenity::Result<u64> {
+    match http::get_current_user()?.guilds()?.first() {
+        Some(guild) => Ok(guild.id.get()?.owner_id.0),
+        None => Err(serenity::Error::Other("Bot is not a member of any guild, or the guild couldn't be found!")),
+    }
+}
+impl EventHandler for Handler {
+    fn message(&self, _: Context, msg: Message) {
+        if msg.author.bot {
+            return;
+        }
+        let command = match Command::parse(&msg.content) {
+            Some(command) => command,
+            None => return,
+        };
+
+        let user = msg.author.id.0;
+        let reply = command.and_then(|c| match c {
+            Command::Favorites => Ok(_favorites(self.state.lock().expect("Arc lock was poisoned in the state").feedback.favorites(user))),
+            ref c if c.is_global() && !self._is_admin(user) =>
+                Err(CommandError("Only the guild owner and the admins in the config can change what everyone gets.".to_string())),
+            c => c.run(&self.config, user),
+        });
+        let reply = match reply {
+            Ok(r) => r,
+            Err(e) => e.to_string(),
+        };
+        if let Err(e) = msg.channel_id.say(reply) {
+            eprintln!("Could not reply to a command: {:?}", e);
+        }
+    }
+    fn reaction_add(&self, _: Context, reaction: Reaction) {
+        let emoji = match reaction.emoji {
+            ReactionType::Unicode(ref emoji) => emoji,
+            _ => return,
+        };
+        let reply = {
+            let mut state = self.state.lock().expect("Arc lock was poisoned in the state");
+            let State { ref messages, ref mut feedback, .. } = *state;
+            match messages.get(reaction.message_id.0) {
+                Some(message) => feedback.react(emoji, reaction.channel_id.0, reaction.user_id.0, message, Utc::now().timestamp()),
+                None => return,
+            }
+        };
+        if let Some(reply) = reply {
+            if let Err(e) = reaction.channel_id.say(reply) {
+                eprintln!("Could not confirm a reaction: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod bot_tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("!watch r/rust async io").unwrap().unwrap(), Command::Watch { subreddit: "rust".to_string(), query: "async io".to_string() });
+        assert_eq!(Command::parse("!unwatch rust").unwrap().unwrap(), Command::Unwatch("rust".to_string()));
+        assert_eq!(Command::parse(" !LIST ").unwrap().unwrap(), Command::List);
+        assert_eq!(Command::parse("!pause").unwrap().unwrap(), Command::Pause(None));
+        assert_eq!(Command::parse("!resume rust").unwrap().unwrap(), Command::Resume(Some("rust".to_string())));
+        assert_eq!(Command::parse("!interval 600s").unwrap().unwrap(), Command::Interval(Duration::from_secs(600)));
+        assert_eq!(Command::parse("!subscribe rust tokio").unwrap().unwrap(), Command::Subscribe { subreddit: "rust".to_string(), query: "tokio".to_string() });
+        assert_eq!(Command::parse("!watch  rust \t async   io ").unwrap().unwrap(), Command::Watch { subreddit: "rust".to_string(), query: "async io".to_string() });
+        assert_eq!(Command::parse("!unwatch  rust").unwrap().unwrap(), Command::Unwatch("rust".to_string()));
+    }
+    #[test]
+    fn only_some_commands_are_global() {
+        assert!(Command::Interval(Duration::from_secs(600)).is_global());
+        assert!(Command::Pause(None).is_global());
+        assert!(!Command::List.is_global());
+        assert!(!Command::Subscribe { subreddit: "rust".to_string(), query: "tokio".to_string() }.is_global());
+    }
+    #[test]
+    fn parse_mistakes() {
+        assert!(Command::parse("hello there").is_none());
+        assert!(Command::parse("!unknown").is_none());
+        assert!(Command::parse("!watch rust").unwrap().is_err());
+        assert!(Command::parse("!interval soon").unwrap().is_err());
+        assert!(Command::parse("!subscribe ../../api/me q").unwrap().is_err());
+        assert!(Command::parse("!watch r/a q").unwrap().is_err());
+    }
+    #[test]
+    fn apply_commands() {
+        let mut config = Config::example(&[]);
+
+        Command::Watch { subreddit: "rust".to_string(), query: "async".to_string() }.apply(&mut config, 1).unwrap();
+        Command::Watch { subreddit: "rust".to_string(), query: "tokio".to_string() }.apply(&mut config, 1).unwrap();
+        Command::Pause(Some("rust".to_string())).apply(&mut config, 1).unwrap();
+        assert_eq!(Command::List.apply(&mut config, 1).unwrap(), "r/rust: \"tokio\" (paused)");
+
+        Command::Interval(Duration::from_secs(60)).apply(&mut config, 1).unwrap();
+        assert_eq!(config.interval, Some(60));
+        assert!(Command::Interval(Duration::from_secs(59)).apply(&mut config, 1).is_err());
+        assert_eq!(config.interval, Some(60));
+        assert!(Command::Pause(Some("python".to_string())).apply(&mut config, 1).is_err());
+
+        Command::Unwatch("rust".to_string()).apply(&mut config, 1).unwrap();
+        assert!(config.subreddit_configs.is_empty());
+        assert!(Command::Unwatch("rust".to_string()).apply(&mut config, 1).is_err());
+    }
+    #[test]
+    fn subscriptions_are_per_user() {
+        let mut config = Config::example(&[]);
+
+        Command::Subscribe { subreddit: "rust".to_string(), query: "async".to_string() }.apply(&mut config, 1).unwrap();
+        Command::Subscribe { subreddit: "python".to_string(), query: "asyncio".to_string() }.apply(&mut config, 2).unwrap();
+        assert_eq!(Command::Subscriptions.apply(&mut config, 1).unwrap(), "r/rust: \"async\"");
+        assert!(config.subreddit_configs.is_empty());
+
+        assert!(Command::Unsubscribe("python".to_string()).apply(&mut config, 1).is_err());
+        Command::Unsubscribe("python".to_string()).apply(&mut config, 2).unwrap();
+        assert!(!config.subscriptions.contains_key(&2));
+        assert_eq!(Command::Subscriptions.apply(&mut config, 2).unwrap(), "You aren't subscribed to anything.");
+    }
+}
@@ -1,33 +1,185 @@
 //! All of the configurations for this project are available in this module, including everything
 //! relating to API tokens, as well as the actual functional, moving parts of the app.
 
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
 use std::path::PathBuf;
 
 use ron;
 use ron::ser::PrettyConfig;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::template::{Template, TemplateError, TemplateKind};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     pub subreddit_configs: Vec<SubredditData>,
+    #[serde(default)]
+    pub subscriptions: HashMap<u64, Vec<SubredditData>>, // Discord user id -> searches only that user is sent
+    #[serde(default)]
+    pub thread_watches: Vec<ThreadWatch>,
+    #[serde(default)]
+    pub tracking: Option<Tracking>,
+    #[serde(default)]
+    pub templates: Templates,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub interval: Option<u64>, // in seconds, overrides the --duration option when set
+    #[serde(default)]
+    pub admins: Vec<u64>, // Discord user ids that may run the global bot commands, besides the guild owner
+    #[serde(default)]
+    pub digests: HashMap<Notifier, Digest>, // notifiers that get their posts in digests instead of after every check
+    #[serde(default)]
+    pub low_priority_digest: Option<Digest>, // how often low priority posts are sent, hourly when left out
+    #[serde(default)]
+    pub urgent_mentions: Mentions, // who's mentioned on Discord when an urgent post comes in
+    #[serde(default)]
+    pub slack: Option<Slack>,
+    #[serde(default)]
+    pub matrix: Option<Matrix>,
+    #[serde(default)]
+    pub email: Option<Email>,
+    #[serde(default)]
+    pub desktop: Option<Desktop>,
+    #[serde(default)]
+    pub feeds: Option<Feeds>,
+    #[serde(default)]
+    pub exec: Option<Exec>,
+    #[serde(default)]
+    pub log: Option<JsonLog>,
     pub discord_bot_token: String,
+    #[serde(default)]
+    pub telegram: Option<Telegram>,
     pub reddit_oauth_id: String,
     pub reddit_oauth_secret: String,
     pub path: PathBuf,
 }
 impl Config {
-    pub fn read(file_path: PathBuf) -> ron::de::Result<Config> {
+    pub fn read(file_path: PathBuf) -> std::result::Result<Config, Box<dyn Error>> {
         let input = fs::read_to_string(file_path)?;
-        ron::de::from_str(input.as_str())
+        let config: Config = ron::de::from_str(input.as_str())?;
+        config.validate()?;
+        Ok(config)
+    }
+    /// Checks the parts of the config that RON can't, like whether the templates make sense and the
+    /// priority rules and thread filters are valid regexes.
+    pub fn validate(&self) -> std::result::Result<(), Box<dyn Error>> {
+        self.templates.validate()?;
+        let subscribed = self.subscriptions.values().flat_map(|subs| subs.iter());
+        for sub in self.subreddit_configs.iter().chain(subscribed) {
+            sub.templates.validate()?;
+            for rule in &sub.priorities {
+                Regex::new(&rule.matches).map_err(|e| format!("Invalid priority rule for r/{}: {}", sub.subreddit, e))?;
+            }
+        }
+        // subscriptions always go straight to Discord, shown like any other posts from their subreddit
+        for (user, subs) in &self.subscriptions {
+            for sub in subs {
+                if !sub.templates.is_empty() || sub.notify.is_some() || sub.digest != Digest::EveryCheck {
+                    return Err(From::from(format!("The subscription of user {} to r/{} can't have its own templates, notify or digest", user, sub.subreddit)));
+                }
+            }
+        }
+        for watch in &self.thread_watches {
+            Regex::new(&watch.filter).map_err(|e| format!("Invalid filter for thread {}: {}", watch.thread, e))?;
+        }
+        Ok(())
+    }
+    /// Picks the template to use for a subreddit (given as it appears in notifications, e.g. r/rust),
+    /// preferring the subreddit's own, then the global one, then the built-in default.
+    pub fn template(&self, subreddit: &str, kind: TemplateKind) -> Template {
+        self.subreddit_configs.iter()
+            .find(|c| subreddit.trim_start_matches("r/") == c.subreddit)
+            .and_then(|c| c.templates.get(kind))
+            .or_else(|| self.templates.get(kind))
+            .and_then(|t| Template::parse(t).ok())
+            .unwrap_or_else(|| Template::default_for(kind))
+    }
+    /// How often a subreddit's posts (keyed as they appear in notifications) are sent out.
+    pub fn subreddit_digest(&self, subreddit: &str) -> Digest {
+        self.subreddit_configs.iter()
+            .find(|c| subreddit.trim_start_matches("r/") == c.subreddit)
+            .map_or(Digest::EveryCheck, |c| c.digest)
+    }
+    /// How often posts are sent to a notifier; email's own `digest` is used unless `digests` says otherwise.
+    pub fn notifier_digest(&self, notifier: Notifier) -> Digest {
+        match (self.digests.get(&notifier), &self.email) {
+            (Some(digest), _) => *digest,
+            (None, Some(email)) if notifier == Notifier::Email => email.digest,
+            _ => Digest::EveryCheck,
+        }
+    }
+    pub fn low_priority_digest(&self) -> Digest {
+        self.low_priority_digest.unwrap_or(Digest::Hourly)
+    }
+    /// Whether a subreddit's posts (keyed as they appear in notifications, e.g. r/rust) should go to
+    /// a notifier; anything without a `notify` list of its own goes everywhere.
+    pub fn notifies(&self, subreddit: &str, notifier: Notifier) -> bool {
+        self.subreddit_configs.iter()
+            .find(|c| subreddit.trim_start_matches("r/") == c.subreddit)
+            .and_then(|c| c.notify.as_ref())
+            .map_or(true, |n| n.contains(&notifier))
+    }
+    /// Takes over what a running config has learned that the file might not know yet: how far each
+    /// search got.
+    pub fn carry_over(&mut self, running: &Config) {
+        _carry_over_cursors(&mut self.subreddit_configs, &running.subreddit_configs);
+        for (user, subs) in self.subscriptions.iter_mut() {
+            if let Some(running) = running.subscriptions.get(user) {
+                _carry_over_cursors(subs, running);
+            }
+        }
+    }
+    /// Whether two configs say the same thing, whatever order their maps happen to be written in.
+    pub fn same_as(&self, other: &Config) -> bool {
+        match (_value(self), _value(other)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
     }
     pub fn write(&self, file_path: &PathBuf) -> std::result::Result<(), Box<Error>> {
         fs::write(file_path, ron::ser::to_string_pretty(self, ron::ser::PrettyConfig { ..PrettyConfig::default() })?.as_bytes())?;
         Ok(())
     }
 }
+#[cfg(test)]
+impl Config {
+    /// A config searching the given subreddits (e.g. "rust") for everything, with nothing else set,
+    /// for tests, which set whatever else they care about on it.
+    pub(crate) fn example(subreddits: &[&str]) -> Config {
+        let mut config: Config = ron::de::from_str(r#"(subreddit_configs: [], discord_bot_token: "", reddit_oauth_id: "",
+            reddit_oauth_secret: "", path: "config.ron")"#).unwrap();
+        config.subreddit_configs = subreddits.iter().map(|sub| SubredditData {
+            subreddit: sub.to_string(),
+            search_query: Query::default(),
+            templates: Default::default(),
+            paused: false,
+            notify: None,
+            digest: Default::default(),
+            priorities: Vec::new(),
+        }).collect();
+        config
+    }
+}
+
+/// A config as JSON values, whose maps are sorted (unlike the config's `HashMap`s). RON's own values
+/// won't do, since they turn every unit enum variant into `()`.
+fn _value(config: &Config) -> Option<serde_json::Value> {
+    serde_json::to_value(config).ok()
+}
+
+fn _carry_over_cursors(subs: &mut [SubredditData], running: &[SubredditData]) {
+    for sub in subs.iter_mut() {
+        if let Some(running) = running.iter().find(|r| r.subreddit == sub.subreddit) {
+            sub.search_query.before = running.search_query.before.clone();
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
@@ -64,4 +216,331 @@ impl Default for Query {
 pub struct SubredditData {
     pub subreddit: String,
     pub search_query: Query,
+    #[serde(default)]
+    pub templates: Templates,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub notify: Option<Vec<Notifier>>, // where this subreddit's posts are sent; everywhere when left out
+    #[serde(default)]
+    pub digest: Digest, // holds this subreddit's posts back to send them together
+    #[serde(default)]
+    pub priorities: Vec<PriorityRule>, // the first rule that matches a post sets its priority, otherwise it's Normal
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PriorityRule {
+    pub matches: String, // regex that the post's title or flair must match
+    pub priority: Priority,
+}
+
+/// Urgent posts skip digests and snoozes and mention people on Discord, while low priority ones
+/// always wait for the low priority digest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    Low,
+    Normal,
+    Urgent,
+}
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Mentions {
+    #[serde(default)]
+    pub users: Vec<u64>,
+    #[serde(default)]
+    pub roles: Vec<u64>,
+}
+impl Mentions {
+    /// The mentions as Discord message text, or `None` if there's no one to mention.
+    pub fn render(&self) -> Option<String> {
+        let mentions: Vec<String> = self.users.iter().map(|u| format!("<@{}>", u))
+            .chain(self.roles.iter().map(|r| format!("<@&{}>", r)))
+            .collect();
+        if mentions.is_empty() { None } else { Some(mentions.join(" ")) }
+    }
+}
+
+/// The places posts can be sent to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Notifier {
+    Discord,
+    Slack,
+    Matrix,
+    Telegram,
+    Email,
+    Desktop,
+    Feed,
+    Exec,
+}
+
+/// Overrides for the built-in notification templates; see the `template` module for placeholders.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Templates {
+    #[serde(default)]
+    pub discord_name: Option<String>,
+    #[serde(default)]
+    pub discord_value: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub slack: Option<String>,
+    #[serde(default)]
+    pub telegram: Option<String>,
+}
+impl Templates {
+    pub fn get(&self, kind: TemplateKind) -> Option<&String> {
+        match kind {
+            TemplateKind::DiscordName => self.discord_name.as_ref(),
+            TemplateKind::DiscordValue => self.discord_value.as_ref(),
+            TemplateKind::Text => self.text.as_ref(),
+            TemplateKind::Slack => self.slack.as_ref(),
+            TemplateKind::Telegram => self.telegram.as_ref(),
+        }
+    }
+    fn is_empty(&self) -> bool {
+        [&self.discord_name, &self.discord_value, &self.text, &self.slack, &self.telegram].iter().all(|t| t.is_none())
+    }
+    fn validate(&self) -> std::result::Result<(), TemplateError> {
+        [&self.discord_name, &self.discord_value, &self.text, &self.slack, &self.telegram].iter()
+            .filter_map(|t| t.as_ref())
+            .map(|t| Template::parse(t).map(|_| ()))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ThreadWatch {
+    pub thread: String, // id of the post whose comments are followed, without the t3_ prefix
+    #[serde(default)]
+    pub filter: String, // regex that comment bodies must match; empty matches everything
+    #[serde(default)]
+    pub top_level_only: bool,
+    pub expire_after: u64, // in seconds, counted from the first check
+    #[serde(default, skip_serializing)]
+    pub started: i64, // only read from configs saved by older versions, the progress is in the state now
+    #[serde(default, skip_serializing)]
+    pub seen: HashSet<String>, // same as `started`
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Tracking {
+    pub window: u64, // in seconds, how long a post is followed after it was sent
+    #[serde(default)]
+    pub score_milestones: Vec<i64>,
+    #[serde(default)]
+    pub comment_milestones: Vec<u64>,
+    #[serde(default)]
+    pub edit_messages: bool, // whether sent messages should be kept up to date with their posts
+    #[serde(default)]
+    pub notify_removals: bool, // whether to report posts that get removed or deleted
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Slack {
+    #[serde(with = "url_serde")]
+    pub webhook: Url, // the incoming webhook's url, from the Slack app's settings
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Matrix {
+    #[serde(with = "url_serde")]
+    pub homeserver: Url, // e.g. https://matrix.org
+    pub access_token: String,
+    pub room: String, // the room's id (e.g. !abcdefg:matrix.org), which the account must have joined
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Telegram {
+    pub bot_token: String, // from @BotFather
+    pub chat_ids: Vec<i64>, // the chats to send to, which the bot must be a member of
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Email {
+    pub server: String, // the SMTP server's host name, e.g. smtp.example.com
+    #[serde(default)]
+    pub port: Option<u16>, // defaults to the usual port for the security used
+    #[serde(default)]
+    pub security: EmailSecurity,
+    #[serde(default)]
+    pub username: String, // no login is attempted when empty
+    #[serde(default)]
+    pub password: String,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub digest: Digest,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum EmailSecurity {
+    Tls, // TLS from the start, usually on port 465
+    StartTls, // upgraded to TLS after connecting, usually on port 587
+    None, // only for servers on the same machine
+}
+impl Default for EmailSecurity {
+    fn default() -> Self {
+        EmailSecurity::Tls
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Desktop {
+    #[serde(default)]
+    pub timeout: Option<u64>, // in seconds, how long notifications stay up; up to the desktop when left out
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Feeds {
+    #[serde(default)]
+    pub directory: Option<PathBuf>, // where the feeds are written after every check
+    #[serde(default)]
+    pub listen: Option<String>, // address to serve the feeds from, e.g. 127.0.0.1:8080
+    #[serde(default)]
+    pub entries: Option<usize>, // how many posts each feed keeps, 50 when left out
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Exec {
+    pub command: Vec<String>, // the program followed by its arguments
+    #[serde(default)]
+    pub batch: bool, // run once per check with all the posts, instead of once per post
+    #[serde(default)]
+    pub input: ExecInput,
+    #[serde(default)]
+    pub timeout: Option<u64>, // in seconds, after which the command is killed; 30 when left out
+}
+
+/// How posts are handed to the command run by the exec sink.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ExecInput {
+    Env,
+    Json,
+    Both,
+}
+impl Default for ExecInput {
+    fn default() -> Self {
+        ExecInput::Both
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct JsonLog {
+    pub path: PathBuf, // the file new posts are appended to, one JSON object per line
+    #[serde(default)]
+    pub max_size: Option<u64>, // in bytes, when the file is rotated; 10MiB when left out
+    #[serde(default)]
+    pub keep: Option<usize>, // how many rotated files are kept, 5 when left out
+}
+
+/// How often posts are sent out; anything but every check holds them back in a digest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Digest {
+    EveryCheck,
+    Hourly,
+    Daily,
+}
+impl Digest {
+    /// How long a digest waits between sends, in seconds.
+    pub fn seconds(self) -> i64 {
+        match self {
+            Digest::EveryCheck => 0,
+            Digest::Hourly => 3600,
+            Digest::Daily => 24 * 3600,
+        }
+    }
+}
+impl Default for Digest {
+    fn default() -> Self {
+        Digest::EveryCheck
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    /// A config with the given subreddit configs (as they'd be written in the file), two users'
+    /// subscriptions and a thread watch.
+    fn config(subreddits: &str) -> Config {
+        let mut config = Config::example(&[]);
+        config.subreddit_configs = ron::de::from_str(&format!("[{}]", subreddits)).unwrap();
+        config.subscriptions = ron::de::from_str(r#"{1: [(subreddit: "rust", search_query: (before: "", q: ""))],
+            2: [(subreddit: "python", search_query: (before: "", q: ""))]}"#).unwrap();
+        config.thread_watches = ron::de::from_str(r#"[(thread: "abc", expire_after: 60)]"#).unwrap();
+        config
+    }
+
+    #[test]
+    fn reloads_keep_what_was_learned() {
+        let mut running = config(r#"(subreddit: "rust", search_query: (before: "", q: "async"))"#);
+        running.subreddit_configs[0].search_query.before = "t3_new".to_string();
+        running.subscriptions.get_mut(&1).unwrap()[0].search_query.before = "t3_sub".to_string();
+
+        // the file as it was first read, which knows none of that
+        let mut fresh = config(r#"(subreddit: "rust", search_query: (before: "", q: "async"))"#);
+        assert!(!fresh.same_as(&running));
+        fresh.carry_over(&running);
+        assert!(fresh.same_as(&running));
+
+        let mut edited = config(r#"(subreddit: "rust", search_query: (before: "", q: "tokio")), (subreddit: "python", search_query: (before: "", q: ""))"#);
+        edited.carry_over(&running);
+        assert!(!edited.same_as(&running));
+        assert_eq!(edited.subreddit_configs[0].search_query.before, "t3_new");
+        assert_eq!(edited.subreddit_configs[1].search_query.before, "");
+        assert_eq!(edited.subscriptions[&1][0].search_query.before, "t3_sub");
+    }
+
+    #[test]
+    fn reloads_see_changed_enum_values() {
+        let running = config(r#"(subreddit: "rust", search_query: (before: "", q: ""), digest: Hourly)"#);
+        assert!(running.same_as(&config(r#"(subreddit: "rust", search_query: (before: "", q: ""), digest: Hourly)"#)));
+        assert!(!running.same_as(&config(r#"(subreddit: "rust", search_query: (before: "", q: ""), digest: Daily)"#)));
+        let slack = config(r#"(subreddit: "rust", search_query: (before: "", q: ""), notify: Some([Slack]))"#);
+        assert!(!slack.same_as(&config(r#"(subreddit: "rust", search_query: (before: "", q: ""), notify: Some([Matrix]))"#)));
+    }
+
+    #[test]
+    fn invalid_patterns_are_turned_away() {
+        assert!(config(r#"(subreddit: "rust", search_query: (before: "", q: ""))"#).validate().is_ok());
+
+        let bad_rule = config(r#"(subreddit: "rust", search_query: (before: "", q: ""), priorities: [(matches: "(unclosed", priority: Urgent)])"#);
+        assert!(bad_rule.validate().unwrap_err().to_string().starts_with("Invalid priority rule for r/rust"));
+
+        let mut bad_filter = config("");
+        bad_filter.thread_watches[0].filter = "[a-".to_string();
+        assert!(bad_filter.validate().unwrap_err().to_string().starts_with("Invalid filter for thread abc"));
+    }
+    #[test]
+    fn subscriptions_only_go_to_discord() {
+        let mut digest = config("");
+        digest.subscriptions.get_mut(&1).unwrap()[0].digest = Digest::Daily;
+        assert!(digest.validate().unwrap_err().to_string().starts_with("The subscription of user 1 to r/rust can't have"));
+
+        let mut notify = config("");
+        notify.subscriptions.get_mut(&2).unwrap()[0].notify = Some(vec![Notifier::Slack]);
+        assert!(notify.validate().is_err());
+
+        let mut templates = config("");
+        templates.subscriptions.get_mut(&1).unwrap()[0].templates.text = Some("{title}".to_string());
+        assert!(templates.validate().is_err());
+    }
 }
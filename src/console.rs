@@ -0,0 +1,80 @@
+//! Commands typed into the console while the monitor is running. The ones that change what's
+//! watched are the bot's commands under other names, and are saved to the config the same way.
+//!
+//! Commands:
+//!     status                   Shows what the monitor is up to
+//!     check now                Checks right away, even when paused
+//!     list                     Lists the searches
+//!     add <subreddit> <query>  Starts (or updates) a search of a subreddit
+//!     remove <subreddit>       Stops searching a subreddit
+//!     pause [subreddit]        Pauses one subreddit, or everything
+//!     resume [subreddit]       Resumes one subreddit, or everything
+//!     interval <duration>      Changes how long to wait between checks (e.g. 600s)
+//!     reload                   Reads the config file again
+//!     stop                     Saves everything and stops the program
+
+use crate::bot::{Command, CommandError};
+
+pub const HELP: &str = "Commands: status, check now, list, add <subreddit> <query>, remove <subreddit>, \
+                        pause [subreddit], resume [subreddit], interval <duration>, reload, help, stop";
+
+#[derive(Debug, PartialEq)]
+pub enum ConsoleCommand {
+    Status,
+    CheckNow,
+    Reload,
+    Help,
+    Stop,
+    Bot(Command), // changes the config just like the bot's command would
+}
+impl ConsoleCommand {
+    pub fn parse(line: &str) -> Result<ConsoleCommand, CommandError> {
+        let line = line.trim();
+        let mut words = line.splitn(2, char::is_whitespace);
+        let name = words.next().unwrap_or_default().to_lowercase();
+        let rest = words.next().unwrap_or_default().trim();
+
+        match (name.as_str(), rest) {
+            ("status", "") => Ok(ConsoleCommand::Status),
+            ("check", now) if now.eq_ignore_ascii_case("now") => Ok(ConsoleCommand::CheckNow),
+            ("reload", "") => Ok(ConsoleCommand::Reload),
+            ("help", "") => Ok(ConsoleCommand::Help),
+            ("stop", "") => Ok(ConsoleCommand::Stop),
+            ("list", _) | ("pause", _) | ("resume", _) | ("interval", _) => _bot(&name, rest),
+            ("add", _) => _bot("watch", rest).map_err(|_| CommandError("Usage: add <subreddit> <query>".to_string())),
+            ("remove", _) => _bot("unwatch", rest).map_err(|_| CommandError("Usage: remove <subreddit>".to_string())),
+            _ => Err(CommandError(format!("Sorry mate, didn't catch that!\n{}", HELP))),
+        }
+    }
+}
+
+/// Reads the rest of the line as one of the bot's commands.
+fn _bot(name: &str, rest: &str) -> Result<ConsoleCommand, CommandError> {
+    match Command::parse(&format!("!{} {}", name, rest)) {
+        Some(command) => command.map(ConsoleCommand::Bot).map_err(|e| CommandError(e.0.replace('!', ""))),
+        None => Err(CommandError(HELP.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod console_tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(ConsoleCommand::parse("status\n").unwrap(), ConsoleCommand::Status);
+        assert_eq!(ConsoleCommand::parse("Check now").unwrap(), ConsoleCommand::CheckNow);
+        assert_eq!(ConsoleCommand::parse("add r/rust async io").unwrap(),
+                   ConsoleCommand::Bot(Command::Watch { subreddit: "rust".to_string(), query: "async io".to_string() }));
+        assert_eq!(ConsoleCommand::parse("remove rust").unwrap(), ConsoleCommand::Bot(Command::Unwatch("rust".to_string())));
+        assert_eq!(ConsoleCommand::parse("pause").unwrap(), ConsoleCommand::Bot(Command::Pause(None)));
+        assert_eq!(ConsoleCommand::parse("interval 600s").unwrap(), ConsoleCommand::Bot(Command::Interval(Duration::from_secs(600))));
+
+        assert_eq!(ConsoleCommand::parse("add rust").unwrap_err().to_string(), "Usage: add <subreddit> <query>");
+        assert_eq!(ConsoleCommand::parse("interval").unwrap_err().to_string(), "Usage: interval <duration>, e.g. interval 600s");
+        assert!(ConsoleCommand::parse("subscribe rust tokio").is_err());
+        assert!(ConsoleCommand::parse("check later").is_err());
+    }
+}
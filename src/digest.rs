@@ -0,0 +1,268 @@
+//! Digests hold new posts back across checks and send them together once their window (hourly or
+//! daily) is up. A subreddit can ask for one, holding its posts back from everywhere, and so can a
+//! notifier, holding back everything sent to it. The posts waiting in them are part of the saved
+//! state, so a restart doesn't lose a digest that's been building up.
+//!
+//! New comments in watched threads only ever wait for a notifier's digest, and are kept apart from
+//! the posts in it, keyed by the thread rather than a subreddit.
+//!
+//! Priorities cut across all of that: urgent posts never wait for a digest, and low priority ones
+//! always do, in a digest of their own.
+//!
+//! Posts that didn't get to a sink wait here too, to be tried again on the next check, but only
+//! where they were missed and only so many times.
+
+use std::collections::HashMap;
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Digest, Notifier, Priority};
+use crate::reddit::Post;
+
+/// How many times a post is tried at a sink before it's given up on.
+pub const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Batch {
+    last_sent: i64, // utc timestamp of when the digest last went out, or started
+    posts: HashMap<String, Vec<Post>>,
+    #[serde(default)]
+    threads: HashMap<String, Vec<Post>>, // thread's permalink -> its new comments
+}
+impl Batch {
+    fn new(now: i64) -> Batch {
+        Batch { last_sent: now, posts: HashMap::new(), threads: HashMap::new() }
+    }
+    fn add(&mut self, posts: HashMap<String, Vec<Post>>, threads: HashMap<String, Vec<Post>>) {
+        _add(&mut self.posts, posts);
+        _add(&mut self.threads, threads);
+    }
+    fn is_empty(&self) -> bool {
+        self.posts.is_empty() && self.threads.is_empty()
+    }
+    fn len(&self) -> usize {
+        self.posts.values().chain(self.threads.values()).map(|list| list.len()).sum()
+    }
+    /// Hands back everything held (posts, then thread posts) if the window is up, starting the next one.
+    fn release(&mut self, window: Digest, now: i64) -> (HashMap<String, Vec<Post>>, HashMap<String, Vec<Post>>) {
+        if self.is_empty() || now - self.last_sent < window.seconds() {
+            return (HashMap::new(), HashMap::new());
+        }
+        self.last_sent = now;
+        (mem::take(&mut self.posts), mem::take(&mut self.threads))
+    }
+}
+
+/// Adds posts to the ones already held, skipping any that are already there.
+fn _add(held: &mut HashMap<String, Vec<Post>>, posts: HashMap<String, Vec<Post>>) {
+    for (key, list) in posts {
+        let held = held.entry(key).or_default();
+        for p in list {
+            if !held.iter().any(|h| h.fullname == p.fullname) {
+                held.push(p);
+            }
+        }
+    }
+}
+
+/// A post that didn't get to a sink, waiting to be tried again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Retry {
+    pub key: String, // subreddit, or the thread's permalink
+    pub thread: bool,
+    pub post: Post,
+    pub to: Option<String>, // the one destination it missed, for sinks with several
+    pub attempts: u32, // how many times it's failed
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Digests {
+    subreddits: HashMap<String, Batch>,
+    notifiers: HashMap<Notifier, Batch>,
+    low_priority: Option<Batch>,
+    retries: HashMap<Notifier, Vec<Retry>>,
+}
+impl Digests {
+    /// Holds back the posts from subreddits that want a digest, handing back the rest along with
+    /// any digests that are due.
+    pub fn subreddits<F: Fn(&str) -> Digest>(&mut self, posts: HashMap<String, Vec<Post>>, window: F, now: i64) -> HashMap<String, Vec<Post>> {
+        let mut ready = HashMap::new();
+        for (sub, list) in posts {
+            if window(&sub) == Digest::EveryCheck && !self.subreddits.contains_key(&sub) {
+                ready.insert(sub, list);
+            } else {
+                let mut held = HashMap::new();
+                held.insert(sub.clone(), list);
+                self.subreddits.entry(sub).or_insert_with(|| Batch::new(now)).add(held, HashMap::new());
+            }
+        }
+        for (sub, batch) in &mut self.subreddits {
+            ready.extend(batch.release(window(sub), now).0);
+        }
+        // subreddits that stopped wanting a digest are forgotten once it's been sent
+        self.subreddits.retain(|sub, batch| !(batch.posts.is_empty() && window(sub) == Digest::EveryCheck));
+        ready
+    }
+    /// Holds back posts and thread posts meant for a notifier that wants a digest, handing back
+    /// whatever should be sent to it now.
+    pub fn notifier(&mut self, notifier: Notifier, posts: HashMap<String, Vec<Post>>, threads: HashMap<String, Vec<Post>>,
+                    window: Digest, now: i64) -> (HashMap<String, Vec<Post>>, HashMap<String, Vec<Post>>) {
+        if window == Digest::EveryCheck && !self.notifiers.contains_key(&notifier) {
+            return (posts, threads);
+        }
+        let batch = self.notifiers.entry(notifier).or_insert_with(|| Batch::new(now));
+        batch.add(posts, threads);
+        let ready = batch.release(window, now);
+        if batch.is_empty() && window == Digest::EveryCheck {
+            self.notifiers.remove(&notifier);
+        }
+        ready
+    }
+    /// Holds back low priority posts, handing back their digest once it's due.
+    pub fn low_priority(&mut self, posts: HashMap<String, Vec<Post>>, window: Digest, now: i64) -> HashMap<String, Vec<Post>> {
+        let batch = self.low_priority.get_or_insert_with(|| Batch::new(now));
+        batch.add(posts, HashMap::new());
+        batch.release(window, now).0
+    }
+    /// Puts back posts that failed at a notifier, so they're tried again on the next check. The ones
+    /// that have failed `MAX_ATTEMPTS` times are given up on instead, and handed back.
+    pub fn retry(&mut self, notifier: Notifier, failed: Vec<Retry>) -> Vec<Retry> {
+        let (dropped, kept): (Vec<Retry>, Vec<Retry>) = failed.into_iter()
+            .map(|r| Retry { attempts: r.attempts + 1, ..r })
+            .partition(|r| r.attempts >= MAX_ATTEMPTS);
+        if !kept.is_empty() {
+            self.retries.entry(notifier).or_default().extend(kept);
+        }
+        dropped
+    }
+    /// Takes the posts waiting to be tried again at a notifier.
+    pub fn retries(&mut self, notifier: Notifier) -> Vec<Retry> {
+        self.retries.remove(&notifier).unwrap_or_default()
+    }
+    /// Gives up on the posts waiting to be tried again at notifiers that aren't set up anymore,
+    /// handing them back.
+    pub fn abandon(&mut self, notifiers: &[Notifier]) -> Vec<(Notifier, Retry)> {
+        let gone: Vec<Notifier> = self.retries.keys().filter(|n| !notifiers.contains(n)).cloned().collect();
+        let retries = &mut self.retries;
+        gone.into_iter()
+            .flat_map(|n| retries.remove(&n).unwrap_or_default().into_iter().map(move |r| (n, r)))
+            .collect()
+    }
+    /// How many posts are waiting in digests, or to be tried again.
+    pub fn held(&self) -> usize {
+        let retries: usize = self.retries.values().map(Vec::len).sum();
+        self.subreddits.values().chain(self.notifiers.values()).chain(self.low_priority.iter()).map(Batch::len).sum::<usize>() + retries
+    }
+}
+
+/// Splits posts into the ones with a priority and the rest, keeping them keyed by subreddit.
+pub fn split(posts: HashMap<String, Vec<Post>>, priority: Priority) -> (HashMap<String, Vec<Post>>, HashMap<String, Vec<Post>>) {
+    let mut matching = HashMap::new();
+    let mut rest = HashMap::new();
+    for (sub, list) in posts {
+        let (m, r): (Vec<Post>, Vec<Post>) = list.into_iter().partition(|p| p.priority == priority);
+        if !m.is_empty() { matching.insert(sub.clone(), m); }
+        if !r.is_empty() { rest.insert(sub, r); }
+    }
+    (matching, rest)
+}
+
+/// Adds posts to the ones already in `into`, keyed by subreddit.
+pub fn merge(into: &mut HashMap<String, Vec<Post>>, posts: HashMap<String, Vec<Post>>) {
+    for (sub, list) in posts {
+        into.entry(sub).or_default().extend(list);
+    }
+}
+
+#[cfg(test)]
+mod digest_tests {
+    use super::*;
+
+    fn posts(sub: &str, ns: &[usize]) -> HashMap<String, Vec<Post>> {
+        let mut posts = HashMap::new();
+        posts.insert(sub.to_string(), ns.iter().map(|n| Post::example(&n.to_string())).collect());
+        posts
+    }
+
+    #[test]
+    fn subreddit_digests_wait_their_turn() {
+        let mut digests = Digests::default();
+        let window = |sub: &str| if sub == "r/rust" { Digest::Hourly } else { Digest::EveryCheck };
+
+        let mut checked = posts("r/rust", &[1]);
+        checked.extend(posts("r/python", &[2]));
+        let ready = digests.subreddits(checked, window, 0);
+        assert_eq!(ready.keys().collect::<Vec<&String>>(), vec!["r/python"]);
+
+        assert!(digests.subreddits(posts("r/rust", &[3]), window, 1800).is_empty());
+        assert_eq!(digests.held(), 2);
+
+        // the digest goes out on the first check after the hour is up, even without new posts
+        let ready = digests.subreddits(HashMap::new(), window, 3600);
+        assert_eq!(ready["r/rust"].iter().map(|p| p.fullname.as_str()).collect::<Vec<&str>>(), vec!["t3_1", "t3_3"]);
+        assert_eq!(digests.held(), 0);
+    }
+    #[test]
+    fn notifier_digests_survive_restarts() {
+        let mut digests = Digests::default();
+        let none = HashMap::new;
+        assert_eq!(digests.notifier(Notifier::Slack, posts("r/rust", &[1]), none(), Digest::EveryCheck, 0).0["r/rust"].len(), 1);
+        assert!(digests.notifier(Notifier::Email, posts("r/rust", &[1]), none(), Digest::Daily, 0).0.is_empty());
+        let (ready, threads) = digests.notifier(Notifier::Email, posts("r/rust", &[1, 2]), posts("r/rust/comments/abc/title", &[4]), Digest::Daily, 60);
+        assert!(ready.is_empty() && threads.is_empty());
+
+        let mut digests: Digests = ron::de::from_str(&ron::ser::to_string(&digests).unwrap()).unwrap();
+        assert_eq!(digests.held(), 3);
+        let (ready, threads) = digests.notifier(Notifier::Email, none(), none(), Digest::Daily, 24 * 3600);
+        assert_eq!(ready.keys().collect::<Vec<&String>>(), vec!["r/rust"]);
+        assert_eq!(ready["r/rust"].len(), 2);
+        assert_eq!(threads["r/rust/comments/abc/title"].len(), 1);
+
+
+        // switching the digest off sends whatever was still waiting
+        assert!(digests.notifier(Notifier::Email, posts("r/rust", &[3]), none(), Digest::Daily, 24 * 3600 + 1).0.is_empty());
+        assert_eq!(digests.notifier(Notifier::Email, none(), none(), Digest::EveryCheck, 24 * 3600 + 2).0["r/rust"].len(), 1);
+        assert_eq!(digests.held(), 0);
+    }
+    #[test]
+    fn failures_are_retried_until_given_up_on() {
+        let mut digests = Digests::default();
+        let failed = |attempts| vec![Retry { key: "r/rust".to_string(), thread: false, post: Post::example("1"), to: Some("2".to_string()), attempts }];
+        assert!(digests.retry(Notifier::Telegram, failed(0)).is_empty());
+        assert_eq!(digests.held(), 1);
+
+        // they're kept across restarts, along with how often they've failed
+        let mut digests: Digests = ron::de::from_str(&ron::ser::to_string(&digests).unwrap()).unwrap();
+        let retries = digests.retries(Notifier::Telegram);
+        assert_eq!((retries[0].to.as_ref().map(String::as_str), retries[0].attempts), (Some("2"), 1));
+        assert!(digests.retries(Notifier::Telegram).is_empty());
+        assert_eq!(digests.held(), 0);
+
+        let dropped = digests.retry(Notifier::Telegram, failed(MAX_ATTEMPTS - 1));
+        assert_eq!(dropped[0].attempts, MAX_ATTEMPTS);
+        assert_eq!(digests.held(), 0);
+
+        // and once their sink is gone, so are they
+        digests.retry(Notifier::Telegram, failed(0));
+        assert!(digests.abandon(&[Notifier::Telegram]).is_empty());
+        assert_eq!(digests.abandon(&[Notifier::Slack])[0].0, Notifier::Telegram);
+        assert_eq!(digests.held(), 0);
+    }
+    #[test]
+    fn priorities_skip_or_wait_for_digests() {
+        let mut checked = posts("r/rust", &[1, 2, 3]);
+        checked.get_mut("r/rust").unwrap()[0].priority = Priority::Urgent;
+        checked.get_mut("r/rust").unwrap()[2].priority = Priority::Low;
+        let (urgent, rest) = split(checked, Priority::Urgent);
+        let (low, mut rest) = split(rest, Priority::Low);
+        assert_eq!((urgent["r/rust"].len(), rest["r/rust"].len(), low["r/rust"].len()), (1, 1, 1));
+
+        let mut digests = Digests::default();
+        assert!(digests.low_priority(low, Digest::Hourly, 0).is_empty());
+        merge(&mut rest, urgent);
+        assert_eq!(rest["r/rust"].iter().map(|p| p.fullname.as_str()).collect::<Vec<&str>>(), vec!["t3_2", "t3_1"]);
+        assert_eq!(digests.low_priority(HashMap::new(), Digest::Hourly, 3600)["r/rust"][0].fullname, "t3_3");
+    }
+}
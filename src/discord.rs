@@ -1,15 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serenity::builder::CreateEmbed;
 use serenity::client::validate_token;
 use serenity::http;
+use serenity::http::{HttpError, StatusCode};
 use serenity::model::channel::{Message, PrivateChannel};
+use serenity::model::id::{ChannelId, MessageId, UserId};
 use serenity::prelude::SerenityError;
 
+use crate::config::{Config, Priority};
+use crate::outbox::Pending;
 use crate::reddit::Post;
+use crate::template::{Template, TemplateKind};
+use crate::tracker::{Tracker, Update};
+use crate::utils::truncate;
 
-pub struct DiscordMessenger(Vec<PrivateChannel>);
+/// The least time left between two messages to the same channel.
+const CHANNEL_GAP: Duration = Duration::from_millis(1000);
+/// How many times a message is tried when Discord keeps rate limiting it.
+const MAX_ATTEMPTS: u32 = 5;
+/// The colour of embeds with urgent posts in them.
+const URGENT_COLOUR: u64 = 0xE7_4C_3C;
+
+pub struct DiscordMessenger {
+    channels: Vec<PrivateChannel>,
+    config: Arc<Mutex<Config>>,
+    last_sent: Mutex<HashMap<ChannelId, Instant>>,
+    opened: Mutex<HashMap<u64, u64>>, // DM channels opened after startup, user id -> channel id
+}
 impl DiscordMessenger {
-    pub fn new(token: String) -> serenity::Result<DiscordMessenger> {
+    pub fn new(config: Arc<Mutex<Config>>) -> serenity::Result<DiscordMessenger> {
+        let token = config.lock().expect("Arc lock was poisoned in the config").discord_bot_token.clone();
         validate_token(&token)?;
 
         let token = if token.starts_with("Bot ") {
@@ -20,7 +46,7 @@ impl DiscordMessenger {
 
         http::set_token(&token);
         match DiscordMessenger::_get_dm_channels() {
-            Ok(channels) => Ok(DiscordMessenger(channels)),
+            Ok(channels) => Ok(DiscordMessenger { channels, config, last_sent: Mutex::new(HashMap::new()), opened: Mutex::new(HashMap::new()) }),
             Err(e) => Err(e)
         }
 
@@ -31,29 +57,351 @@ impl DiscordMessenger {
             None => Err(serenity::Error::Other("Bot is not a member of any guild, or the guild couldn't be found!"))
         }
     }
+    /// The (field name, field value) templates for a subreddit's embeds.
+    fn _templates(&self, sub: &str) -> (Template, Template) {
+        let config = self.config.lock().expect("Arc lock was poisoned in the config");
+        (config.template(sub, TemplateKind::DiscordName), config.template(sub, TemplateKind::DiscordValue))
+    }
 
     pub fn send(&self, post: &Post) -> Result<Vec<Message>, SerenityError> {
-        self.0.iter().map(|c| c.send_message(|m| m.embed(|em| em.title(&post.title).url(&post.link)))).collect()
-    }
-    pub fn send_all(&self, posts: HashMap<String, Vec<Post>>) -> Result<Vec<Vec<Message>>, SerenityError> {
-        // posts.iter().map(|p| self.send(p)).collect()
-
-        posts.iter().map(|(sub, list)|
-            self.0.iter().map(|dm|
-                dm.send_message(|m|
-                    m.embed(|em|
-                        em
-                            .title(&sub)
-                            .url(format!("https://old.reddit.com/{}", &sub).as_str())
-                            .fields(list.iter().map(|p: &Post| (
-                                 &p.title,
-                                 format!("[{:+}] [{} comment{}] [link]({})\n*posted {} ago*", p.score, &p.comments, if p.comments == 1 { "" } else { "s" }, p.link, humantime::format_duration(p.posted)),
-                                 false // inline or not
-                            )))
-                    )
-                )
-            ).collect()
-        ).collect()
+        self.channels.iter().map(|c| c.send_message(|m| m.embed(|em| em.title(&post.title).url(&post.link)))).collect()
+    }
+    /// The DM channel ids of everyone who gets notifications.
+    pub fn recipients(&self) -> Vec<u64> {
+        self.channels.iter().map(|c| c.id.0).collect()
+    }
+    /// The DM channel id of a user, opening one if they didn't get one at startup (like someone who
+    /// joined the guild since).
+    pub fn channel_for(&self, user: u64) -> serenity::Result<u64> {
+        if let Some(channel) = self.channels.iter().find(|c| c.recipient.read().id.0 == user) {
+            return Ok(channel.id.0);
+        }
+        let mut opened = self.opened.lock().expect("Mutex was poisoned in the opened DM channels");
+        if let Some(channel) = opened.get(&user) {
+            return Ok(*channel);
+        }
+        let channel = UserId(user).create_dm_channel()?.id.0;
+        opened.insert(user, channel);
+        Ok(channel)
+    }
+    /// Sends every subreddit's posts to everyone; see `send_each`.
+    pub fn send_all(&self, posts: &HashMap<String, Vec<Post>>) -> Vec<Delivery> {
+        let everyone: Pending = self.recipients().into_iter().map(|c| (c, posts.clone())).collect();
+        self.send_each(&everyone)
+    }
+    /// Sends each recipient the posts they're owed, splitting every subreddit's posts over as many
+    /// embeds as Discord's limits call for (one message each), and reports how each message went.
+    /// Messages with urgent posts in them mention whoever the config says to.
+    pub fn send_each(&self, pending: &Pending) -> Vec<Delivery> {
+        let mentions = self.config.lock().expect("Arc lock was poisoned in the config").urgent_mentions.render();
+        let mut deliveries = Vec::new();
+        let mut chunks = Vec::new();
+        let mut jobs = Vec::new();
+        for (channel, posts) in pending {
+            for (sub, list) in posts {
+                let list: Vec<&Post> = list.iter().collect();
+                for chunk in _chunk_fields(sub, _render_fields(&list, &self._templates(sub))) {
+                    let mention = if chunk.iter().any(|f| f.urgent) { mentions.clone() } else { None };
+                    jobs.push(Job { delivery: deliveries.len(), channel: ChannelId(*channel), edit: None, mention });
+                    deliveries.push(Delivery::new(sub, chunk.iter().map(|f| f.fullname.clone()).collect()));
+                    chunks.push(chunk);
+                }
+            }
+        }
+
+        let outcomes = self._run_queue(jobs, |i, em| _subreddit_embed(em, &deliveries[i].subreddit, &chunks[i]));
+        _sort_outcomes(deliveries, outcomes)
+    }
+    /// Sends each update to the channels it's meant for.
+    pub fn send_updates(&self, updates: &[(Update, Vec<u64>)]) -> Vec<Delivery> {
+        let deliveries: Vec<Delivery> = updates.iter().map(|(u, _)| Delivery::new(&u.subreddit, vec![u.post.fullname.clone()])).collect();
+        let titles: Vec<String> = updates.iter().map(|(u, _)| truncate(&self._templates(&u.subreddit).0.render(&u.post), EMBED_MAX_TITLE)).collect();
+        let jobs = updates.iter().enumerate()
+            .flat_map(|(i, (_, channels))| channels.iter().map(move |c| Job { delivery: i, channel: ChannelId(*c), edit: None, mention: None }))
+            .collect();
+
+        let outcomes = self._run_queue(jobs, |i, em| {
+            let update = &updates[i].0;
+            em
+                .title(&titles[i])
+                .url(update.post.permalink.as_str())
+                .description(format!("This post in {} {}!", &update.subreddit, update.kind))
+        });
+        _sort_outcomes(deliveries, outcomes)
+    }
+    /// Re-renders sent messages with the latest versions of their posts.
+    pub fn edit_changed(&self, edits: &[Edit]) -> Vec<Delivery> {
+        let mut deliveries = Vec::new();
+        let mut chunks = Vec::new();
+        let mut jobs = Vec::new();
+        for edit in edits {
+            let posts: Vec<&Post> = edit.posts.iter().collect();
+            // the posts fit when they were sent, but they might have grown a few characters since, in
+            // which case the message is left as it is rather than edited without some of them
+            let fields = match _fit(&edit.message.subreddit, _render_fields(&posts, &self._templates(&edit.message.subreddit))) {
+                Some(fields) => fields,
+                None => { eprintln!("Not editing message {}, as its posts don't fit in it anymore.", edit.id); continue; },
+            };
+
+            jobs.push(Job { delivery: deliveries.len(), channel: ChannelId(edit.message.channel_id), edit: Some(MessageId(edit.id)), mention: None });
+            deliveries.push(Delivery::new(&edit.message.subreddit, edit.message.posts.clone()));
+            chunks.push(fields);
+        }
+
+        let outcomes = self._run_queue(jobs, |i, em| _subreddit_embed(em, &deliveries[i].subreddit, &chunks[i]));
+        _sort_outcomes(deliveries, outcomes)
+    }
+
+    /// Works through the jobs one at a time, making sure no channel gets messages more often than
+    /// `CHANNEL_GAP` allows (other channels' jobs go first in the meantime), and waiting out any
+    /// rate limits Discord still reports. Failed jobs don't hold up the rest.
+    fn _run_queue<F>(&self, jobs: Vec<Job>, embed: F) -> Vec<(usize, ChannelId, serenity::Result<Message>)>
+        where F: Fn(usize, CreateEmbed) -> CreateEmbed {
+        let mut last_sent = self.last_sent.lock().expect("Mutex was poisoned in the delivery queue");
+        let mut queue: VecDeque<Job> = jobs.into_iter().collect();
+        let mut outcomes = Vec::new();
+
+        while let Some(job) = queue.pop_front() {
+            if let Some(wait) = _wait_for(&last_sent, job.channel) {
+                if queue.iter().any(|j| _wait_for(&last_sent, j.channel).is_none()) {
+                    queue.push_back(job);
+                    continue;
+                }
+                thread::sleep(wait);
+            }
+
+            let mut attempts = 0;
+            let result = loop {
+                attempts += 1;
+                let result = match job.edit {
+                    Some(id) => job.channel.edit_message(id, |m| m.embed(|em| embed(job.delivery, em))),
+                    None => job.channel.send_message(|m| match job.mention {
+                        Some(ref mention) => m.content(mention).embed(|em| embed(job.delivery, em)),
+                        None => m.embed(|em| embed(job.delivery, em)),
+                    }),
+                };
+                last_sent.insert(job.channel, Instant::now());
+
+                match result {
+                    Err(e) => match _retry_after(e) {
+                        Ok(wait) if attempts < MAX_ATTEMPTS => thread::sleep(wait),
+                        Ok(_) => break Err(SerenityError::Other("Gave up on a message after being rate limited too many times")),
+                        Err(e) => break Err(e),
+                    },
+                    ok => break ok,
+                }
+            };
+            outcomes.push((job.delivery, job.channel, result));
+        }
+
+        outcomes
+    }
+}
+
+struct Job {
+    delivery: usize, // index of the delivery this job is a part of
+    channel: ChannelId,
+    edit: Option<MessageId>, // the message to edit, if any, rather than sending a new one
+    mention: Option<String>, // sent along with a new message, to ping people about urgent posts
+}
+
+/// How long a channel still needs to be left alone for, if at all.
+fn _wait_for(last_sent: &HashMap<ChannelId, Instant>, channel: ChannelId) -> Option<Duration> {
+    let ready = *last_sent.get(&channel)? + CHANNEL_GAP;
+    let now = Instant::now();
+    if ready > now { Some(ready - now) } else { None }
+}
+
+#[derive(Deserialize)]
+struct RateLimited {
+    retry_after: u64, // in milliseconds
+}
+
+/// Works out how long Discord wants us to wait if the error is a rate limit, or hands it back otherwise.
+fn _retry_after(e: SerenityError) -> Result<Duration, SerenityError> {
+    match e {
+        SerenityError::Http(HttpError::UnsuccessfulRequest(mut response)) => {
+            if response.status == StatusCode::TooManyRequests {
+                let wait = serde_json::from_reader::<_, RateLimited>(&mut response).map(|r| r.retry_after).unwrap_or(1000);
+                Ok(Duration::from_millis(wait))
+            } else {
+                Err(SerenityError::Http(HttpError::UnsuccessfulRequest(response)))
+            }
+        },
+        e => Err(e),
+    }
+}
+
+fn _sort_outcomes(mut deliveries: Vec<Delivery>, outcomes: Vec<(usize, ChannelId, serenity::Result<Message>)>) -> Vec<Delivery> {
+    outcomes.into_iter().for_each(|(i, channel, result)| deliveries[i].outcomes.push((channel.0, result)));
+    deliveries
+}
+
+/// How sending one message's worth of posts went, for every recipient.
+#[derive(Debug)]
+pub struct Delivery {
+    pub subreddit: String,
+    pub posts: Vec<String>, // fullnames of the posts in the message
+    pub outcomes: Vec<(u64, serenity::Result<Message>)>, // recipient channel id -> what happened
+}
+impl Delivery {
+    fn new(subreddit: &str, posts: Vec<String>) -> Delivery {
+        Delivery { subreddit: subreddit.to_string(), posts, outcomes: Vec::new() }
+    }
+    pub fn sent(&self) -> Vec<&Message> {
+        self.outcomes.iter().filter_map(|(_, r)| r.as_ref().ok()).collect()
+    }
+    pub fn failures(&self) -> Vec<(u64, &SerenityError)> {
+        self.outcomes.iter().filter_map(|(c, r)| r.as_ref().err().map(|e| (*c, e))).collect()
+    }
+}
+
+// Discord's limits on embeds, see https://discordapp.com/developers/docs/resources/channel#embed-limits
+const EMBED_MAX_TITLE: usize = 256;
+const EMBED_MAX_FIELDS: usize = 25;
+const EMBED_MAX_FIELD_NAME: usize = 256;
+const EMBED_MAX_FIELD_VALUE: usize = 1024;
+const EMBED_MAX_TOTAL: usize = 6000;
+
+#[derive(Debug, Clone, Default)]
+struct EmbedField {
+    fullname: String,
+    name: String,
+    value: String,
+    urgent: bool,
+}
+
+fn _render_fields(posts: &[&Post], (name, value): &(Template, Template)) -> Vec<EmbedField> {
+    posts.iter().map(|p| {
+        let urgent = p.priority == Priority::Urgent;
+        let (n, v) = match p.removal {
+            None if urgent => (format!("🚨 {}", name.render(p)), value.render(p)),
+            None => (name.render(p), value.render(p)),
+            Some(ref removal) => (format!("~~{}~~", name.render(p)), format!("*this post {}*", removal)),
+        };
+        EmbedField {
+            fullname: p.fullname.clone(),
+            name: truncate(&n, EMBED_MAX_FIELD_NAME),
+            value: truncate(&v, EMBED_MAX_FIELD_VALUE),
+            urgent,
+        }
+    }).collect()
+}
+
+/// Packs fields into as few embeds as possible without breaking the field count or total size limits.
+fn _chunk_fields(title: &str, fields: Vec<EmbedField>) -> Vec<Vec<EmbedField>> {
+    let title_len = truncate(title, EMBED_MAX_TITLE).chars().count();
+    let mut chunks: Vec<Vec<EmbedField>> = Vec::new();
+    let mut size = 0;
+
+    for field in fields {
+        let field_len = field.name.chars().count() + field.value.chars().count();
+        match chunks.last_mut() {
+            Some(ref mut chunk) if chunk.len() < EMBED_MAX_FIELDS && size + field_len <= EMBED_MAX_TOTAL => {
+                size += field_len;
+                chunk.push(field);
+            },
+            _ => {
+                size = title_len + field_len;
+                chunks.push(vec![field]);
+            },
+        }
+    }
+
+    chunks
+}
+
+/// The fields, if they still fit in a single embed.
+fn _fit(title: &str, fields: Vec<EmbedField>) -> Option<Vec<EmbedField>> {
+    let mut chunks = _chunk_fields(title, fields);
+    if chunks.len() == 1 { chunks.pop() } else { None }
+}
+
+fn _subreddit_embed(em: CreateEmbed, sub: &str, fields: &[EmbedField]) -> CreateEmbed {
+    let em = if fields.iter().any(|f| f.urgent) { em.colour(URGENT_COLOUR) } else { em };
+    em
+        .title(truncate(sub, EMBED_MAX_TITLE))
+        .url(format!("https://old.reddit.com/{}", sub).as_str())
+        .fields(fields.iter().map(|f| (
+            &f.name,
+            &f.value,
+            false // inline or not
+        )))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SentMessage {
+    pub channel_id: u64,
+    pub subreddit: String,
+    pub posts: Vec<String>, // fullnames of the posts in the embed, in order
+    #[serde(default)]
+    pub sent_utc: i64,
+}
+
+/// A sent message to re-render, with the latest versions of the posts in it.
+pub struct Edit {
+    pub id: u64,
+    pub message: SentMessage,
+    pub posts: Vec<Post>,
+}
+
+/// Remembers which Discord messages every post went out in, so they can be edited (or reacted to)
+/// later on.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SentMessages {
+    posts: HashMap<String, Vec<u64>>, // post fullname -> ids of the messages it's in
+    messages: HashMap<u64, SentMessage>, // message id -> where it went and what it shows
+}
+impl SentMessages {
+    pub fn record(&mut self, subreddit: &str, posts: &[String], messages: &[&Message]) {
+        let now = Utc::now().timestamp();
+        for message in messages {
+            self.messages.insert(message.id.0, SentMessage {
+                channel_id: message.channel_id.0,
+                subreddit: subreddit.to_string(),
+                posts: posts.to_vec(),
+                sent_utc: now,
+            });
+            posts.iter().for_each(|p| self.posts.entry(p.clone()).or_insert_with(Vec::new).push(message.id.0));
+        }
+    }
+    pub fn containing(&self, fullname: &str) -> Vec<(u64, &SentMessage)> {
+        self.posts.get(fullname)
+            .map(|ids| ids.iter().filter_map(|id| self.messages.get(id).map(|m| (*id, m))).collect())
+            .unwrap_or_default()
+    }
+    pub fn get(&self, id: u64) -> Option<&SentMessage> {
+        self.messages.get(&id)
+    }
+    /// The channels a post was sent to, each once.
+    pub fn channels_with(&self, fullname: &str) -> Vec<u64> {
+        let mut channels: Vec<u64> = self.containing(fullname).into_iter().map(|(_, m)| m.channel_id).collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+    /// The sent messages that show one of the `changed` posts, each with the latest versions of its
+    /// posts kept by the tracker. Messages with posts the tracker has stopped following are left as
+    /// they are, since they'd lose those posts.
+    pub fn edits(&self, tracker: &Tracker, changed: &[String]) -> Vec<Edit> {
+        let mut edited = HashSet::new();
+        changed.iter()
+            .flat_map(|fullname| self.containing(fullname))
+            .filter(|(id, _)| edited.insert(*id))
+            .filter_map(|(id, message)| {
+                let posts: Option<Vec<Post>> = message.posts.iter().map(|f| tracker.get(f).map(|t| t.post.clone())).collect();
+                posts.map(|posts| Edit { id, message: message.clone(), posts })
+            })
+            .collect()
+    }
+    /// Forgets messages sent more than `max_age` seconds ago, along with posts left in none of them.
+    pub fn expire(&mut self, max_age: i64) {
+        let now = Utc::now().timestamp();
+        self.messages.retain(|_, m| now - m.sent_utc < max_age);
+        let messages = &self.messages;
+        self.posts.values_mut().for_each(|ids| ids.retain(|id| messages.contains_key(id)));
+        self.posts.retain(|_, ids| !ids.is_empty());
     }
 }
 
@@ -61,40 +409,88 @@ impl DiscordMessenger {
 mod discord_tests {
     use std::path::PathBuf;
     use std::time::Duration;
-    use url::Url;
-    use crate::config::Config;
+
+    use crate::config::Tracking;
+
     use super::*;
 
+    fn post(n: u8, score: i64, comments: u64, posted: u64) -> Post {
+        Post { score, comments, posted: Duration::from_secs(posted), ..Post::example(&format!("post{}", n)) }
+    }
+
     #[test]
     fn send_message() {
-        let config = Config::read(PathBuf::from("config.ron")).unwrap();
-        let messenger = DiscordMessenger::new(config.discord_bot_token);
+        let config = Arc::new(Mutex::new(Config::read(PathBuf::from("config.ron")).unwrap()));
+        let messenger = DiscordMessenger::new(config);
 
         let mut posts = HashMap::new();
         posts.insert(String::from("r/rust"), vec![
-            Post {
-                title: "Post #1".to_string(),
-                link: Url::parse("https://old.reddit.com/r/rust").unwrap(),
-                score: 2,
-                comments: 1,
-                posted: Duration::from_secs(30)
-            },
-            Post {
-                title: "Post #2".to_string(),
-                link: Url::parse("https://old.reddit.com/r/rust").unwrap(),
-                score: -3,
-                comments: 0,
-                posted: Duration::from_secs(3600)
-            },
-            Post {
-                title: "Post #3".to_string(),
-                link: Url::parse("https://old.reddit.com/r/rust").unwrap(),
-                score: 0,
-                comments: 15,
-                posted: Duration::from_secs(0)
-            },
+            post(1, 2, 1, 30),
+            post(2, -3, 0, 3600),
+            post(3, 0, 15, 0),
         ]);
 
-        assert!(messenger.unwrap().send_all(posts).is_ok());
+        assert!(messenger.unwrap().send_all(&posts).iter().all(|d| d.failures().is_empty()));
+    }
+    #[test]
+    fn fields_are_truncated() {
+        let mut long = post(1, 0, 0, 0);
+        long.title = "μ".repeat(300);
+        let templates = (Template::default_for(TemplateKind::DiscordName), Template::default_for(TemplateKind::DiscordValue));
+        let fields = _render_fields(&[&long], &templates);
+
+        assert_eq!(fields[0].name.chars().count(), EMBED_MAX_FIELD_NAME);
+        assert!(fields[0].name.ends_with('…'));
+    }
+    #[test]
+    fn urgent_posts_stand_out() {
+        let urgent = Post { priority: Priority::Urgent, ..post(1, 0, 0, 0) };
+        let templates = (Template::default_for(TemplateKind::DiscordName), Template::default_for(TemplateKind::DiscordValue));
+        let fields = _render_fields(&[&urgent, &post(2, 0, 0, 0)], &templates);
+
+        assert!(fields[0].urgent && fields[0].name.starts_with("🚨 "));
+        assert!(!fields[1].urgent && !fields[1].name.starts_with("🚨"));
+    }
+    #[test]
+    fn chunks_respect_embed_limits() {
+        let field = |n: usize, len: usize| EmbedField { fullname: n.to_string(), name: "n".repeat(len), value: String::new(), urgent: false };
+
+        let many: Vec<EmbedField> = (0..60).map(|n| field(n, 10)).collect();
+        let chunks = _chunk_fields("r/rust", many);
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![25, 25, 10]);
+
+        let big: Vec<EmbedField> = (0..10).map(|n| field(n, 1000)).collect();
+        let chunks = _chunk_fields("r/rust", big);
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![5, 5]);
+        assert_eq!(chunks.concat().iter().map(|f| f.fullname.as_str()).collect::<Vec<_>>(), vec!["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"]);
+    }
+    #[test]
+    fn edits_keep_every_post() {
+        let field = |n: usize, len: usize| EmbedField { fullname: n.to_string(), name: "n".repeat(len), value: String::new(), urgent: false };
+        assert_eq!(_fit("r/rust", (0..5).map(|n| field(n, 1000)).collect()).map(|f| f.len()), Some(5));
+        assert!(_fit("r/rust", (0..7).map(|n| field(n, 1000)).collect()).is_none());
+
+        let mut sent = SentMessages::default();
+        let message = |posts: &[&str]| SentMessage {
+            channel_id: 1, subreddit: "r/rust".to_string(), posts: posts.iter().map(|p| p.to_string()).collect(), sent_utc: 0,
+        };
+        sent.messages.insert(10, message(&["t3_post1", "t3_post2"]));
+        sent.messages.insert(11, message(&["t3_post1", "t3_gone"]));
+        sent.posts.insert("t3_post1".to_string(), vec![10, 11]);
+
+        let tracking = Tracking { window: 3600, score_milestones: vec![], comment_milestones: vec![], edit_messages: true, notify_removals: false };
+        let mut tracker = Tracker::default();
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![post(1, 0, 0, 0), post(2, 0, 0, 0)]);
+        tracker.track(&posts, &tracking);
+
+        sent.messages.get_mut(&11).unwrap().channel_id = 2;
+        assert_eq!(sent.channels_with("t3_post1"), vec![1, 2]);
+        assert!(sent.channels_with("t3_post2").is_empty());
+
+        // the second message has a post that isn't tracked anymore, so it's left alone
+        let edits = sent.edits(&tracker, &["t3_post1".to_string()]);
+        assert_eq!(edits.iter().map(|e| e.id).collect::<Vec<u64>>(), vec![10]);
+        assert_eq!(edits[0].posts.len(), 2);
     }
 }
@@ -0,0 +1,139 @@
+//! Reacting to a sent notification gives quick feedback on it: ❌ mutes the follow-ups (milestones
+//! and removals) of the posts in it, 🔕 snoozes its subreddit for an hour and ⭐ saves its posts to
+//! the favorites. Mutes and snoozes only apply to whoever reacted, and are kept in the saved state.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Priority;
+use crate::discord::SentMessage;
+use crate::outbox::Pending;
+use crate::reddit::Post;
+
+pub const MUTE: &str = "❌";
+pub const SNOOZE: &str = "🔕";
+pub const FAVORITE: &str = "⭐";
+/// How long a snooze lasts, in seconds.
+const SNOOZE_FOR: i64 = 3600;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Favorite {
+    pub fullname: String,
+    pub subreddit: String,
+    pub saved_utc: i64,
+}
+impl Favorite {
+    pub fn permalink(&self) -> String {
+        format!("https://old.reddit.com/comments/{}", self.fullname.trim_start_matches("t3_"))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Feedback {
+    muted: HashMap<u64, HashSet<String>>, // channel id -> fullnames of the posts to send no more follow-ups for
+    snoozed: HashMap<u64, HashMap<String, i64>>, // channel id -> subreddit -> utc timestamp the snooze ends at
+    favorites: HashMap<u64, Vec<Favorite>>, // user id -> saved posts, oldest first
+}
+impl Feedback {
+    /// Acts on a reaction to one of the sent messages, returning a confirmation for whoever reacted,
+    /// or `None` if the emoji doesn't mean anything.
+    pub fn react(&mut self, emoji: &str, channel: u64, user: u64, message: &SentMessage, now: i64) -> Option<String> {
+        match emoji.trim_end_matches('\u{fe0f}') {
+            MUTE => {
+                self.muted.entry(channel).or_insert_with(HashSet::new).extend(message.posts.iter().cloned());
+                Some(format!("Muted further updates on {} post(s) from {}.", message.posts.len(), message.subreddit))
+            },
+            SNOOZE => {
+                self.snoozed.entry(channel).or_insert_with(HashMap::new).insert(message.subreddit.clone(), now + SNOOZE_FOR);
+                Some(format!("Snoozed {} for an hour.", message.subreddit))
+            },
+            FAVORITE => {
+                let favorites = self.favorites.entry(user).or_insert_with(Vec::new);
+                for fullname in &message.posts {
+                    if !favorites.iter().any(|f| &f.fullname == fullname) {
+                        favorites.push(Favorite { fullname: fullname.clone(), subreddit: message.subreddit.clone(), saved_utc: now });
+                    }
+                }
+                Some(format!("Saved {} post(s) from {} to your favorites.", message.posts.len(), message.subreddit))
+            },
+            _ => None,
+        }
+    }
+    pub fn is_muted(&self, channel: u64, fullname: &str) -> bool {
+        self.muted.get(&channel).map_or(false, |muted| muted.contains(fullname))
+    }
+    pub fn is_snoozed(&self, channel: u64, subreddit: &str, now: i64) -> bool {
+        self.snoozed.get(&channel).and_then(|s| s.get(subreddit)).map_or(false, |until| *until > now)
+    }
+    /// The pending posts that can be sent right now, leaving out snoozed subreddits (except for
+    /// their urgent posts).
+    pub fn due(&self, pending: &Pending, now: i64) -> Pending {
+        pending.iter()
+            .map(|(channel, posts)| (*channel, posts.iter()
+                .filter_map(|(sub, list)| {
+                    if !self.is_snoozed(*channel, sub, now) {
+                        return Some((sub.clone(), list.clone()));
+                    }
+                    let urgent: Vec<Post> = list.iter().filter(|p| p.priority == Priority::Urgent).cloned().collect();
+                    if urgent.is_empty() { None } else { Some((sub.clone(), urgent)) }
+                })
+                .collect::<HashMap<_, _>>()))
+            .filter(|(_, posts)| !posts.is_empty())
+            .collect()
+    }
+    /// Forgets snoozes that are over, and mutes on posts that no longer pass `keep`.
+    pub fn expire<F: Fn(&str) -> bool>(&mut self, now: i64, keep: F) {
+        self.snoozed.values_mut().for_each(|s| s.retain(|_, until| *until > now));
+        self.snoozed.retain(|_, s| !s.is_empty());
+        self.muted.values_mut().for_each(|m| m.retain(|fullname| keep(fullname)));
+        self.muted.retain(|_, m| !m.is_empty());
+    }
+    pub fn favorites(&self, user: u64) -> &[Favorite] {
+        self.favorites.get(&user).map_or(&[], |f| f.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod feedback_tests {
+    use super::*;
+
+    fn message() -> SentMessage {
+        SentMessage { channel_id: 1, subreddit: "r/rust".to_string(), posts: vec!["t3_a".to_string(), "t3_b".to_string()], sent_utc: 0 }
+    }
+
+    #[test]
+    fn reactions_only_affect_whoever_reacted() {
+        let mut feedback = Feedback::default();
+        assert!(feedback.react("👍", 1, 10, &message(), 0).is_none());
+
+        feedback.react(MUTE, 1, 10, &message(), 0).unwrap();
+        assert!(feedback.is_muted(1, "t3_a") && !feedback.is_muted(2, "t3_a"));
+
+        feedback.react("⭐\u{fe0f}", 1, 10, &message(), 0).unwrap();
+        feedback.react(FAVORITE, 1, 10, &message(), 5).unwrap();
+        assert_eq!(feedback.favorites(10).len(), 2);
+        assert!(feedback.favorites(20).is_empty());
+        assert_eq!(feedback.favorites(10)[0].permalink(), "https://old.reddit.com/comments/a");
+
+        feedback.expire(0, |fullname| fullname != "t3_a");
+        assert!(!feedback.is_muted(1, "t3_a") && feedback.is_muted(1, "t3_b"));
+    }
+    #[test]
+    fn snoozed_subreddits_wait_their_turn() {
+        let mut feedback = Feedback::default();
+        feedback.react(SNOOZE, 1, 10, &message(), 100).unwrap();
+
+        let mut pending = Pending::new();
+        for channel in &[1, 2] {
+            pending.entry(*channel).or_insert_with(HashMap::new).insert("r/rust".to_string(), Vec::new());
+        }
+        let due = feedback.due(&pending, 200);
+        assert!(!due.contains_key(&1) && due.contains_key(&2));
+
+        assert_eq!(feedback.due(&pending, 100 + SNOOZE_FOR).len(), 2);
+        feedback.expire(100 + SNOOZE_FOR, |_| true);
+        assert!(!feedback.is_snoozed(1, "r/rust", 0));
+    }
+}
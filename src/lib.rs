@@ -7,10 +7,21 @@ extern crate regex;
 extern crate url;
 extern crate url_serde;
 
+pub mod bot;
 pub mod config;
+pub mod console;
+pub mod digest;
 pub mod discord;
+pub mod feedback;
+pub mod log;
 pub mod monitor;
+pub mod outbox;
 pub mod reddit;
+pub mod sinks;
+pub mod state;
+pub mod template;
+pub mod threads;
+pub mod tracker;
 pub mod utils;
 
 pub use monitor::Monitor;
@@ -0,0 +1,122 @@
+//! An append-only log of every new post, one JSON object per line, for auditing or analysing later.
+//! Each line says where the post came from and what happened to it everywhere it was sent, so it's
+//! written at the end of a check rather than as one of the sinks. The file is rotated once it gets
+//! too big, keeping a few of the old ones around (e.g. `posts.jsonl.1`, `posts.jsonl.2`).
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::config::JsonLog;
+use crate::reddit::Post;
+
+const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_KEEP: usize = 5;
+
+#[derive(Serialize, Debug)]
+pub struct LogEntry<'a> {
+    pub logged_utc: i64,
+    pub subreddit: &'a str, // or the thread's permalink, for thread watches
+    pub query: Option<&'a str>, // the search that found the post, if any
+    pub user: Option<u64>, // whose subscription found the post, if it was one
+    pub post: &'a Post,
+    pub delivery: BTreeMap<String, String>, // notifier -> what happened there
+}
+impl<'a> LogEntry<'a> {
+    pub fn new(subreddit: &'a str, query: Option<&'a str>, user: Option<u64>, post: &'a Post, delivery: BTreeMap<String, String>) -> LogEntry<'a> {
+        LogEntry { logged_utc: Utc::now().timestamp(), subreddit, query, user, post, delivery }
+    }
+}
+
+pub struct JsonLinesLog {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+}
+impl JsonLinesLog {
+    pub fn new(log: &JsonLog) -> JsonLinesLog {
+        JsonLinesLog {
+            path: log.path.clone(),
+            max_size: log.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+            keep: log.keep.unwrap_or(DEFAULT_KEEP),
+        }
+    }
+    pub fn append(&self, entries: &[LogEntry]) -> Result<(), Box<dyn Error>> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + lines.len() as u64 > self.max_size {
+            self._rotate()?;
+        }
+        OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+    fn _rotated(&self, n: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), n))
+    }
+    /// Moves every old log one number up (dropping the oldest), and the current one to `.1`.
+    fn _rotate(&self) -> Result<(), Box<dyn Error>> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        if self._rotated(self.keep).exists() {
+            fs::remove_file(self._rotated(self.keep))?;
+        }
+        for n in (1..self.keep).rev() {
+            if self._rotated(n).exists() {
+                fs::rename(self._rotated(n), self._rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self._rotated(1))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod log_tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn appends_lines_and_rotates() {
+        let dir = env::temp_dir().join(format!("{}-jsonl-test", env!("CARGO_PKG_NAME")));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let log = JsonLinesLog::new(&JsonLog { path: dir.join("posts.jsonl"), max_size: Some(1000), keep: Some(2) });
+
+        let post = Post { created_utc: 1558000000, ..Post::example("abc") };
+        let mut delivery = BTreeMap::new();
+        delivery.insert("Discord".to_string(), "sent to 1 recipient(s)".to_string());
+        let entry = LogEntry::new("r/rust", Some("async"), None, &post, delivery);
+
+        log.append(&[entry]).unwrap();
+        let line: serde_json::Value = serde_json::from_str(fs::read_to_string(dir.join("posts.jsonl")).unwrap().lines().next().unwrap()).unwrap();
+        assert_eq!(line["query"], "async");
+        assert_eq!(line["post"]["fullname"], "t3_abc");
+        assert_eq!(line["delivery"]["Discord"], "sent to 1 recipient(s)");
+
+        let entry = LogEntry::new("r/rust", Some("async"), None, &post, BTreeMap::new());
+        for _ in 0..10 {
+            log.append(&[LogEntry { delivery: BTreeMap::new(), ..entry }]).unwrap();
+        }
+        assert!(fs::metadata(dir.join("posts.jsonl")).unwrap().len() <= 1000);
+        assert!(dir.join("posts.jsonl.1").exists() && dir.join("posts.jsonl.2").exists());
+        assert!(!dir.join("posts.jsonl.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,6 +5,7 @@
 //!     redditing-buddy [FLAGS] [OPTIONS]
 //!
 //! FLAGS:
+//!         --daemon     Runs without reading the console, until stopped with SIGINT or SIGTERM
 //!     -h, --help       Prints help information
 //!     -V, --version    Prints version information
 //!
@@ -16,13 +17,20 @@
 #[macro_use] extern crate structopt;
 
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
 use std::time::Duration;
 
 use chrono::Local;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
 use structopt::StructOpt;
 
 use redditing_buddy::config::Config;
+use redditing_buddy::console::{ConsoleCommand, HELP};
 use redditing_buddy::Monitor;
 use redditing_buddy::utils::{file_exists, parse_duration};
 
@@ -36,25 +44,68 @@ struct Options {
     /// Interstitial duration for checking Reddit
     #[structopt(short, long, default_value = "600s", parse(try_from_str = "parse_duration"))]
     duration: Duration,
+
+    /// Runs without reading the console, until stopped with SIGINT or SIGTERM
+    #[structopt(long)]
+    daemon: bool,
+}
+
+/// What the main thread waits on while the monitor runs.
+enum Event {
+    Line(String), // from the console
+    ConsoleClosed,
+    Stop, // asked to stop by a signal
+    Reload, // the config file changed, or SIGHUP asked for it to be read again
 }
 
+/// How long the config file has to be left alone before it's reloaded, so that it isn't read while
+/// it's still being written.
+const CONFIG_SETTLE: Duration = Duration::from_secs(2);
+
 fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     let options: Options = Options::from_args();
     let config = Config::read(options.config)?;
+    let config_path = config.path.clone();
 
     let mut monitor = Monitor::new(config, options.duration);
     monitor.start()?;
 
     println!("Server started at {}", Local::now());
-    println!("Enter \"stop\" to stop the program.");
-    loop { // await console input and break when told to stop
-        let mut buf = String::new();
-        if let Err(e) = io::stdin().read_line(&mut buf) {
-            eprintln!("Error: {}", e);
-        }
+    let (events, received) = mpsc::channel();
+    _listen_for_signals(events.clone())?;
+    let _watcher = match _watch_config(&config_path, events.clone()) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            eprintln!("Could not watch the config file for changes, send SIGHUP or enter \"reload\" to reload it instead: {}", e);
+            None
+        },
+    };
+    if options.daemon {
+        println!("Running as a daemon, send SIGINT or SIGTERM to stop the program.");
+    } else {
+        println!("Enter \"help\" to see what the console can do, or \"stop\" to stop the program.");
+        _listen_to_console(events);
+    }
 
-        if buf.trim().to_lowercase() == "stop" { break; }
-        else { println!("Sorry mate, didn't catch that!\nIf you want to stop, enter \"stop\" into the console."); }
+    for event in received { // await console input or a signal and break when told to stop
+        match event {
+            Event::Stop => break,
+            Event::Reload => _reload(&monitor, false),
+            Event::Line(ref line) if line.trim().is_empty() => {},
+            Event::Line(line) => match ConsoleCommand::parse(&line) {
+                Ok(ConsoleCommand::Stop) => break,
+                Ok(ConsoleCommand::Status) => println!("{}", monitor.status()),
+                Ok(ConsoleCommand::CheckNow) => { monitor.check_now(); println!("Checking now..."); },
+                Ok(ConsoleCommand::Reload) => _reload(&monitor, true),
+                Ok(ConsoleCommand::Help) => println!("{}", HELP),
+                Ok(ConsoleCommand::Bot(command)) => match monitor.apply(command) {
+                    Ok(reply) => println!("{}", reply),
+                    Err(e) => eprintln!("{}", e),
+                },
+                Err(e) => eprintln!("{}", e),
+            },
+            Event::ConsoleClosed => println!("The console was closed, send SIGINT or SIGTERM to stop the program."),
+        }
     }
     println!("Stopping the monitor...");
 
@@ -63,3 +114,70 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     println!("Server stopped at {}", Local::now());
     Ok(())
 }
+
+/// Reloads the config, saying so only if something changed (or the reload was asked for).
+fn _reload(monitor: &Monitor, asked: bool) {
+    match monitor.reload() {
+        Ok(true) => println!("Reloaded the config at {}", Local::now()),
+        Ok(false) if asked => println!("The config hasn't changed."),
+        Ok(false) => {},
+        Err(e) => eprintln!("Could not reload the config, the old one is still in use: {}", e),
+    }
+}
+
+/// Asks for a reload whenever the config file changes. Editors often save by replacing the file
+/// rather than writing to it, so it's the directory that's watched.
+fn _watch_config(path: &Path, events: Sender<Event>) -> notify::Result<RecommendedWatcher> {
+    let path = path.canonicalize()?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher: RecommendedWatcher = Watcher::new(tx, CONFIG_SETTLE)?;
+    watcher.watch(path.parent().unwrap_or(&path), RecursiveMode::NonRecursive)?;
+    thread::spawn(move || {
+        for event in rx {
+            let changed = match event {
+                DebouncedEvent::Create(ref p) | DebouncedEvent::Write(ref p) | DebouncedEvent::Rename(_, ref p) => *p == path,
+                _ => false,
+            };
+            if changed && events.send(Event::Reload).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(watcher)
+}
+
+/// Reads the console on its own thread, passing each line on until it's closed (e.g. when running
+/// without a TTY, under systemd or in Docker).
+fn _listen_to_console(events: Sender<Event>) {
+    thread::spawn(move || loop {
+        let mut buf = String::new();
+        let (event, closed) = match io::stdin().read_line(&mut buf) {
+            Ok(0) => (Event::ConsoleClosed, true),
+            Ok(_) => (Event::Line(buf), false),
+            Err(e) => { eprintln!("Could not read the console: {}", e); (Event::ConsoleClosed, true) },
+        };
+        if events.send(event).is_err() || closed {
+            break;
+        }
+    });
+}
+
+/// Turns SIGINT and SIGTERM into a request to stop, so the config and state get saved on the way out,
+/// and SIGHUP into a request to reload the config.
+#[cfg(unix)]
+fn _listen_for_signals(events: Sender<Event>) -> io::Result<()> {
+    let signals = Signals::new(&[signal_hook::SIGINT, signal_hook::SIGTERM, signal_hook::SIGHUP])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let event = if signal == signal_hook::SIGHUP { Event::Reload } else { Event::Stop };
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+#[cfg(not(unix))]
+fn _listen_for_signals(_: Sender<Event>) -> io::Result<()> {
+    Ok(())
+}
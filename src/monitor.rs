@@ -1,52 +1,584 @@
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+use chrono::Utc;
 use clokwerk::{ScheduleHandle, Scheduler, TimeUnits};
+use serenity::client::Client;
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::prelude::Mutex as SerenityMutex;
 
-use crate::config::Config;
-use crate::discord::DiscordMessenger;
-use crate::reddit::Redditor;
+use crate::bot::{Command, CommandError, Handler};
+use crate::config::{Config, Notifier, Priority, SubredditData, Tracking};
+use crate::digest;
+use crate::digest::Retry;
+use crate::discord::{Delivery, DiscordMessenger, SentMessages};
+use crate::feedback::Feedback;
+use crate::log::{JsonLinesLog, LogEntry};
+use crate::reddit::{Post, Redditor};
+use crate::sinks;
+use crate::sinks::Sink;
+use crate::state::State;
+use crate::tracker::Update;
+
+/// How long sent messages are remembered for (so they can be reacted to), unless tracking needs them
+/// for longer; in seconds.
+const MESSAGE_MEMORY: i64 = 7 * 24 * 3600;
 
 pub struct Monitor {
     config: Arc<Mutex<Config>>,
-    interval: Duration,
+    state: Arc<Mutex<State>>,
+    interval: Duration, // used unless the config has an interval of its own
     handle: Option<ScheduleHandle>,
+    gateway: Option<(Arc<SerenityMutex<ShardManager>>, JoinHandle<()>)>,
+    check_now: Arc<AtomicBool>, // set to check on the next tick, whatever the interval or pausing says
+    last_checked: Arc<AtomicI64>, // utc timestamp of the latest check, 0 until the first one
+    sinks: Arc<Mutex<Vec<Box<dyn Sink>>>>, // set up again when a reload changes their sections
 
 } impl Monitor {
     pub fn new(config: Config, duration: Duration) -> Monitor {
         Monitor {
             config: Arc::new(Mutex::new(config)),
+            state: Arc::new(Mutex::new(State::default())),
             interval: duration,
             handle: None,
+            gateway: None,
+            check_now: Arc::new(AtomicBool::new(false)),
+            last_checked: Arc::new(AtomicI64::new(0)),
+            sinks: Arc::new(Mutex::new(Vec::new())),
         }
     }
     pub fn start(&mut self) -> Result<(), Box<dyn Error>> {
         let mut scheduler = Scheduler::new();
-        let d = DiscordMessenger::new(self.config.lock().expect("Could not get a lock on the config in `start`").discord_bot_token.clone())?;
+        let state_path = {
+            let mut config = self.config.lock().expect("Could not get a lock on the config in `start`");
+            let state_path = State::path_for(&config.path);
+            let mut state = State::read(&state_path)?;
+            state.threads.adopt(&mut config.thread_watches);
+            state.threads.retain(&config.thread_watches);
+            *self.state.lock().expect("Could not get a lock on the state in `start`") = state;
+            state_path
+        };
+        let d = DiscordMessenger::new(Arc::clone(&self.config))?;
         let mut r = Redditor::new(Arc::clone(&self.config))?;
+        *self.sinks.lock().expect("Mutex was poisoned in the sinks") = sinks::from_config(&self.config)?;
+        let sinks = Arc::clone(&self.sinks);
+        let shared_state = Arc::clone(&self.state);
+        let config = Arc::clone(&self.config);
+        let default_interval = self.interval;
+        let mut last_check = Instant::now();
+        let check_now = Arc::clone(&self.check_now);
+        let last_checked = Arc::clone(&self.last_checked);
+
+        // the scheduler ticks every second, so that changes to the interval or pausing (e.g. through
+        // bot commands or a reload) take effect right away; everything else is read from the config
+        // on every check for the same reason
+        scheduler.every(1.seconds()).run(move || {
+            {
+                let config = config.lock().expect("Arc lock was poisoned in the config");
+                let interval = config.interval.map(Duration::from_secs).unwrap_or(default_interval);
+                if !check_now.swap(false, Ordering::SeqCst) && (config.paused || last_check.elapsed() < interval) {
+                    return;
+                }
+            }
+            last_check = Instant::now();
+            last_checked.store(Utc::now().timestamp(), Ordering::SeqCst);
+            {
+                let mut state = shared_state.lock().expect("Arc lock was poisoned in the state");
+                let mut config = config.lock().expect("Arc lock was poisoned in the config");
+                state.threads.expire(&mut config.thread_watches, Utc::now().timestamp());
+            }
 
-        scheduler.every((self.interval.as_secs() as u32).seconds()).run(move || {
             let new_posts = r.check();
+            let thread_comments = r.check_threads();
+            let subscribed = r.check_subscriptions();
+            let now = Utc::now().timestamp();
+            let (new_posts, thread_posts) = {
+                let mut state = shared_state.lock().expect("Arc lock was poisoned in the state");
+                let thread_posts = state.threads.record(thread_comments, now);
+
+                // subreddits that want a digest only hand their posts on once it's due; urgent posts
+                // skip every digest, and low priority ones always wait for their own
+                let config = config.lock().expect("Arc lock was poisoned in the config");
+                let (urgent, rest) = digest::split(new_posts, Priority::Urgent);
+                let (low, rest) = digest::split(rest, Priority::Low);
+                let mut ready = state.digests.subreddits(rest, |sub| config.subreddit_digest(sub), now);
+                digest::merge(&mut ready, state.digests.low_priority(low, config.low_priority_digest(), now));
+                digest::merge(&mut ready, urgent);
+                (ready, thread_posts)
+            };
+            let ((discord_posts, discord_threads), sink_statuses, dropped) =
+                _notify_sinks(&sinks, &config, &shared_state, &new_posts, &thread_posts, now);
+            let tracking = config.lock().expect("Arc lock was poisoned in the config").tracking.clone();
+
+            {
+                let mut state = shared_state.lock().expect("Arc lock was poisoned in the state");
+                state.messages.expire(tracking.as_ref().map_or(MESSAGE_MEMORY, |t| MESSAGE_MEMORY.max(t.window as i64)));
+                let State { ref mut feedback, ref tracker, .. } = *state;
+                feedback.expire(now, |fullname| tracker.get(fullname).is_some());
+            }
+            if let Some(ref tracking) = tracking {
+                _follow_up(&mut r, &d, &shared_state, tracking);
+                shared_state.lock().expect("Arc lock was poisoned in the state").tracker.track(&discord_posts, tracking);
+            }
+
+            // everything goes through the outbox, and only leaves it once it's been delivered;
+            // subscribers without a DM channel from startup (e.g. who joined since) get one opened
+            let recipients = d.recipients();
+            let subscribers: Vec<u64> = config.lock().expect("Arc lock was poisoned in the config").subscriptions.keys().cloned().collect();
+            let opened: Vec<(u64, u64)> = subscribers.into_iter()
+                .filter_map(|user| match d.channel_for(user) {
+                    Ok(channel) => Some((user, channel)),
+                    Err(e) => { eprintln!("Could not open a DM channel with user {} for their subscriptions: {:?}", user, e); None },
+                })
+                .collect();
+            let mut state = shared_state.lock().expect("Arc lock was poisoned in the state");
+            let mut channels = recipients.clone();
+            for (user, channel) in opened {
+                channels.push(channel);
+                if let Some(posts) = subscribed.get(&user) {
+                    state.outbox.push(&[channel], posts);
+                }
+            }
+            state.outbox.retain_recipients(&channels);
+            state.outbox.push(&recipients, &discord_posts);
+            state.outbox.push(&recipients, &discord_threads);
+
+            // snoozed subreddits stay in the outbox until the snooze is over; the state is let go of
+            // while sending, so reactions and bot commands don't wait out Discord's rate limits
+            let due = state.feedback.due(state.outbox.pending(), now);
+            drop(state);
+            let deliveries = d.send_each(&due);
+            let mut state = shared_state.lock().expect("Arc lock was poisoned in the state");
+            for delivery in &deliveries {
+                delivery.outcomes.iter()
+                    .filter(|(_, result)| result.is_ok())
+                    .for_each(|(channel, _)| state.outbox.confirm(*channel, &delivery.posts));
+                state.messages.record(&delivery.subreddit, &delivery.posts, &delivery.sent());
+            }
+            _report_failures(&deliveries);
 
-            if let Err(e) = d.send_all(new_posts) {
-                eprintln!("{:?}", e);
+            if let Err(e) = state.write(&state_path) {
+                eprintln!("Could not save the state: {}", e);
+            }
+            drop(state);
+
+            let config = config.lock().expect("Arc lock was poisoned in the config");
+            if let Some(ref log) = config.log {
+                let entries = _log_entries(&config, &new_posts, &thread_posts, &subscribed, &sink_statuses, &dropped, &deliveries);
+                if let Err(e) = JsonLinesLog::new(log).append(&entries) {
+                    eprintln!("Could not write to the post log: {}", e);
+                }
             }
         });
-        self.handle = Some(scheduler.watch_thread(self.interval));
+        self.handle = Some(scheduler.watch_thread(Duration::from_millis(500)));
+
+        let token = self.config.lock().expect("Could not get a lock on the config in `start`").discord_bot_token.clone();
+        let mut client = Client::new(&token, Handler::new(Arc::clone(&self.config), Arc::clone(&self.state)))?;
+        let shard_manager = Arc::clone(&client.shard_manager);
+        self.gateway = Some((shard_manager, thread::spawn(move || {
+            if let Err(e) = client.start() {
+                eprintln!("The Discord bot stopped listening for commands: {:?}", e);
+            }
+        })));
 
         Ok(())
     }
+    /// Checks on the next tick of the scheduler (within a second), even when paused.
+    pub fn check_now(&self) {
+        self.check_now.store(true, Ordering::SeqCst);
+    }
+    /// Runs one of the bot's commands against the running config, as if it came from Discord.
+    pub fn apply(&self, command: Command) -> Result<String, CommandError> {
+        command.run(&self.config, 0)
+    }
+    /// Reads the config file again, keeping how far the searches have got (and leaving out thread
+    /// watches that have already expired), and sets up any sinks whose sections changed. The running
+    /// config (and its sinks) stay as they are if the file can't be read, isn't valid, or has a sink
+    /// that can't be set up. Hands back whether anything changed, since saving the config (e.g. after
+    /// a bot command) looks like an edit too.
+    ///
+    /// The Discord bot token is only read when starting, so changing it still needs a restart.
+    pub fn reload(&self) -> Result<bool, Box<dyn Error>> {
+        let path = self.config.lock().expect("Arc lock was poisoned in the config").path.clone();
+        let mut fresh = Config::read(path.clone())?;
+        fresh.path = path;
+
+        let mut state = self.state.lock().expect("Arc lock was poisoned in the state");
+        let mut sinks = self.sinks.lock().expect("Mutex was poisoned in the sinks");
+        let mut config = self.config.lock().expect("Arc lock was poisoned in the config");
+        fresh.carry_over(&config);
+        state.threads.adopt(&mut fresh.thread_watches);
+        let listed = fresh.thread_watches.clone();
+        state.threads.expire(&mut fresh.thread_watches, Utc::now().timestamp());
+        if fresh.same_as(&config) {
+            return Ok(false);
+        }
+        sinks::reload(&mut sinks, &config, &fresh, &self.config)?;
+        state.threads.retain(&listed);
+        *config = fresh;
+        Ok(true)
+    }
+    /// Describes what the monitor is up to.
+    pub fn status(&self) -> String {
+        let watching = {
+            let config = self.config.lock().expect("Arc lock was poisoned in the config");
+            let interval = config.interval.map(Duration::from_secs).unwrap_or(self.interval);
+            format!(
+                "Checking every {}{}.\nWatching {} subreddit(s) ({} paused), {} thread(s) and {} subscription(s).",
+                humantime::format_duration(interval), if config.paused { ", but everything is paused" } else { "" },
+                config.subreddit_configs.len(), config.subreddit_configs.iter().filter(|c| c.paused).count(),
+                config.thread_watches.len(), config.subscriptions.values().map(|s| s.len()).sum::<usize>(),
+            )
+        };
+        let waiting = {
+            let state = self.state.lock().expect("Arc lock was poisoned in the state");
+            let owed: usize = state.outbox.pending().values().flat_map(|posts| posts.values()).map(|list| list.len()).sum();
+            format!("{} post(s) waiting to be delivered, and {} held back for digests.", owed, state.digests.held())
+        };
+        let checked = match self.last_checked.load(Ordering::SeqCst) {
+            0 => "Not checked yet.".to_string(),
+            utc => format!("Last checked {} ago.", humantime::format_duration(Duration::from_secs((Utc::now().timestamp() - utc).max(0) as u64))),
+        };
+        format!("{}\n{}\n{}", watching, checked, waiting)
+    }
     pub fn stop(self) -> Result<(), Box<dyn Error>> {
         if let Some(h) = self.handle {
             h.stop();
+            if let Some((shard_manager, gateway)) = self.gateway {
+                shard_manager.lock().shutdown_all();
+                if gateway.join().is_err() {
+                    eprintln!("The Discord bot's thread panicked while shutting down.");
+                }
+            }
 
-            match self.config.lock() {
-                Ok(config) => config.write(&config.path),
-                Err(_) => Err(From::from("Could not get a lock for the config; this is likely because the scheduling thread panicked and poisoned the guard."))
+            match (self.config.lock(), self.state.lock()) {
+                (Ok(config), Ok(state)) => {
+                    state.write(&State::path_for(&config.path))?;
+                    config.write(&config.path)
+                },
+                _ => Err(From::from("Could not get a lock for the config or state; this is likely because the scheduling thread panicked and poisoned the guard."))
             }
         } else {
             Err(From::from("This Monitor has already been stopped."))
         }
     }
 }
+
+/// Catches up on the posts that were already sent: stops following old ones, reports milestones and
+/// removals and, if asked to, edits the sent messages to match the posts' latest state. The state is
+/// only held while working out what to send, and while noting what was.
+fn _follow_up(r: &mut Redditor, d: &DiscordMessenger, shared_state: &Mutex<State>, tracking: &Tracking) {
+    let fullnames = {
+        let mut state = shared_state.lock().expect("Arc lock was poisoned in the state");
+        state.tracker.expire(tracking);
+        state.tracker.fullnames()
+    };
+
+    let fresh = match r.info(&fullnames) {
+        Ok(fresh) => fresh,
+        Err(e) => { eprintln!("{:?}", e); return; }
+    };
+    let (edits, updates) = {
+        let mut state = shared_state.lock().expect("Arc lock was poisoned in the state");
+        let changed = state.tracker.changed(&fresh);
+        let updates = state.tracker.refresh(fresh, tracking);
+        let edits = if tracking.edit_messages { state.messages.edits(&state.tracker, &changed) } else { Vec::new() };
+        (edits, _update_recipients(updates, &state.messages, &state.feedback))
+    };
+
+    let edited = d.edit_changed(&edits);
+    let deliveries = d.send_updates(&updates);
+    let mut state = shared_state.lock().expect("Arc lock was poisoned in the state");
+    deliveries.iter().for_each(|delivery| state.messages.record(&delivery.subreddit, &delivery.posts, &delivery.sent()));
+    _report_failures(&edited);
+    _report_failures(&deliveries);
+}
+
+/// Every update, with the channels its post was sent to that haven't muted it or snoozed its subreddit.
+fn _update_recipients(updates: Vec<Update>, sent: &SentMessages, feedback: &Feedback) -> Vec<(Update, Vec<u64>)> {
+    let now = Utc::now().timestamp();
+    updates.into_iter()
+        .map(|u| {
+            let wanting = sent.channels_with(&u.post.fullname).into_iter()
+                .filter(|c| !feedback.is_muted(*c, &u.post.fullname) && !feedback.is_snoozed(*c, &u.subreddit, now))
+                .collect();
+            (u, wanting)
+        })
+        .collect()
+}
+
+/// What happened to each subreddit's posts at every sink: subreddit -> notifier -> outcome.
+type SinkStatuses = HashMap<String, BTreeMap<String, String>>;
+/// The new posts and thread posts meant for Discord.
+type ForDiscord = (HashMap<String, Vec<Post>>, HashMap<String, Vec<Post>>);
+/// The posts that were given up on at a sink, with why.
+type Dropped = Vec<(Notifier, Retry, String)>;
+
+/// Sends the new posts to every sink that wants them (or holds them back for its digest), and tries
+/// the ones they missed before again. Hands back the posts meant for Discord, how the sinks took the
+/// new ones, and which posts they were given up on. Thread posts are kept apart from the rest all the
+/// way through, since a thread's permalink looks a lot like a subreddit.
+///
+/// The state is let go of while sending, so reactions and bot commands don't wait on slow sinks; the
+/// sinks are kept until then, so they can't be changed by a reload in the meantime.
+fn _notify_sinks(sinks: &Mutex<Vec<Box<dyn Sink>>>, config: &Arc<Mutex<Config>>, state: &Mutex<State>, new_posts: &HashMap<String, Vec<Post>>,
+                 thread_posts: &HashMap<String, Vec<Post>>, now: i64) -> (ForDiscord, SinkStatuses, Dropped) {
+    let mut held = state.lock().expect("Arc lock was poisoned in the state");
+    let sinks = sinks.lock().expect("Mutex was poisoned in the sinks");
+    let digests = &mut held.digests;
+    let notifiers: Vec<Notifier> = sinks.iter().map(|s| s.notifier()).collect();
+    let mut dropped: Dropped = digests.abandon(&notifiers).into_iter()
+        .map(|(notifier, r)| (notifier, r, "dropped, the sink isn't set up anymore".to_string()))
+        .collect();
+
+    let (per_sink, for_discord) = {
+        let config = config.lock().expect("Arc lock was poisoned in the config");
+        let mut per_sink = Vec::new();
+        for notifier in notifiers.iter().cloned().chain(Some(Notifier::Discord)) {
+            let selected = sinks::select(&config, new_posts, notifier);
+            let keys: Vec<String> = selected.keys().chain(thread_posts.keys()).cloned().collect();
+            let (urgent, selected) = digest::split(selected, Priority::Urgent);
+            let (mut posts, threads) = digests.notifier(notifier, selected, thread_posts.clone(), config.notifier_digest(notifier), now);
+            digest::merge(&mut posts, urgent);
+            per_sink.push((keys, posts, threads, digests.retries(notifier)));
+        }
+        let (_, posts, threads, _) = per_sink.pop().expect("Discord is always last");
+        (per_sink, (posts, threads))
+    };
+    drop(held);
+
+    let mut statuses = SinkStatuses::new();
+    let mut failed = Vec::new();
+    for (sink, (keys, posts, threads, retries)) in sinks.iter().zip(per_sink) {
+        let mut sent = HashMap::new();
+        // what was missed before goes first, so what's missed now waits for the next check
+        let mut missed = _resend(sink.as_ref(), retries);
+        missed.extend(_send(sink.as_ref(), posts, false, &mut sent));
+        missed.extend(_send(sink.as_ref(), threads, true, &mut sent));
+        failed.push((sink.notifier(), missed));
+        for key in keys {
+            let status = sent.remove(&key).unwrap_or_else(|| "held for the digest".to_string());
+            statuses.entry(key).or_default().insert(format!("{:?}", sink.notifier()), status);
+        }
+    }
+    drop(sinks);
+
+    let mut state = state.lock().expect("Arc lock was poisoned in the state");
+    for (notifier, missed) in failed {
+        _give_up(notifier, state.digests.retry(notifier, missed), &mut dropped);
+    }
+    (for_discord, statuses, dropped)
+}
+
+/// Sends posts (or thread posts) to a sink, handing back the ones it missed so they can be tried
+/// again on the next check. How it went is noted for every subreddit or thread in `sent`.
+fn _send(sink: &dyn Sink, posts: HashMap<String, Vec<Post>>, threads: bool, sent: &mut HashMap<String, String>) -> Vec<Retry> {
+    if posts.is_empty() {
+        return Vec::new();
+    }
+    let result = if threads { sink.send_threads(&posts) } else { sink.send(&posts) };
+    let undelivered = match result {
+        Ok(()) => {
+            sent.extend(posts.keys().map(|key| (key.clone(), "sent".to_string())));
+            return Vec::new();
+        },
+        Err(undelivered) => undelivered,
+    };
+    eprintln!("Could not send posts to {:?}: {}", sink.notifier(), undelivered);
+    for key in posts.keys() {
+        let status = if undelivered.posts.iter().any(|m| &m.key == key) { format!("failed: {}", undelivered) } else { "sent".to_string() };
+        sent.insert(key.clone(), status);
+    }
+    undelivered.posts.into_iter()
+        .map(|m| Retry { key: m.key, thread: threads, post: m.post, to: m.to, attempts: 0 })
+        .collect()
+}
+
+/// Tries the posts that a sink missed on earlier checks again, only where they were missed, handing
+/// back the ones it missed again.
+fn _resend(sink: &dyn Sink, retries: Vec<Retry>) -> Vec<Retry> {
+    let mut grouped: BTreeMap<(Option<String>, bool), Vec<Retry>> = BTreeMap::new();
+    for retry in retries {
+        grouped.entry((retry.to.clone(), retry.thread)).or_default().push(retry);
+    }
+    let mut missed = Vec::new();
+    for ((to, threads), list) in grouped {
+        let mut posts: HashMap<String, Vec<Post>> = HashMap::new();
+        list.iter().for_each(|r| posts.entry(r.key.clone()).or_default().push(r.post.clone()));
+        let result = match to {
+            Some(ref to) => sink.send_to(to, &posts, threads),
+            None if threads => sink.send_threads(&posts),
+            None => sink.send(&posts),
+        };
+        if let Err(undelivered) = result {
+            eprintln!("Could not send posts to {:?} again: {}", sink.notifier(), undelivered);
+            missed.extend(list.into_iter().filter(|r| undelivered.missed(&r.key, &r.post.fullname)));
+        }
+    }
+    missed
+}
+
+fn _give_up(notifier: Notifier, given_up: Vec<Retry>, dropped: &mut Dropped) {
+    for r in given_up {
+        eprintln!("Gave up on sending {} from {} to {:?} after {} attempts.", r.post.fullname, r.key, notifier, r.attempts);
+        let why = format!("dropped after {} failed attempts", r.attempts);
+        dropped.push((notifier, r, why));
+    }
+}
+
+/// One log entry for every post found in this check, with what happened to it everywhere it went.
+fn _log_entries<'a>(config: &'a Config, new_posts: &'a HashMap<String, Vec<Post>>, thread_posts: &'a HashMap<String, Vec<Post>>,
+                    subscribed: &'a HashMap<u64, HashMap<String, Vec<Post>>>, sink_statuses: &SinkStatuses, dropped: &'a Dropped,
+                    deliveries: &[Delivery]) -> Vec<LogEntry<'a>> {
+    let discord = _discord_statuses(deliveries);
+    let delivery = |sub: Option<&str>, post: &Post, to_discord: bool| {
+        let mut delivery = sub.and_then(|s| sink_statuses.get(s)).cloned().unwrap_or_default();
+        if to_discord {
+            // posts that weren't sent are waiting in the outbox, e.g. for a snooze to end
+            let status = discord.get(&post.fullname).cloned().unwrap_or_else(|| "held back".to_string());
+            delivery.insert(format!("{:?}", Notifier::Discord), status);
+        }
+        delivery
+    };
+    let query = |searches: &'a [SubredditData], sub: &str| searches.iter()
+        .find(|c| sub.trim_start_matches("r/") == c.subreddit)
+        .map(|c| c.search_query.q.as_str());
+
+    let mut entries = Vec::new();
+    for (sub, list) in new_posts {
+        for post in list {
+            let delivery = delivery(Some(sub), post, config.notifies(sub, Notifier::Discord));
+            entries.push(LogEntry::new(sub, query(&config.subreddit_configs, sub), None, post, delivery));
+        }
+    }
+    for (thread, list) in thread_posts {
+        for post in list {
+            entries.push(LogEntry::new(thread, None, None, post, delivery(Some(thread), post, config.notifies(thread, Notifier::Discord))));
+        }
+    }
+    for (user, posts) in subscribed {
+        let searches = config.subscriptions.get(user).map_or(&[][..], |s| s.as_slice());
+        for (sub, list) in posts {
+            for post in list {
+                // subscriptions only go to Discord
+                let delivery = delivery(None, post, true);
+                entries.push(LogEntry::new(sub, query(searches, sub), Some(*user), post, delivery));
+            }
+        }
+    }
+    // posts from earlier checks that a sink was given up on get one more entry, saying so
+    for (notifier, r, why) in dropped {
+        let delivery = vec![(format!("{:?}", notifier), why.clone())].into_iter().collect();
+        entries.push(LogEntry::new(&r.key, None, None, &r.post, delivery));
+    }
+    entries
+}
+
+/// How sending each post (by fullname) to Discord went in this check.
+fn _discord_statuses(deliveries: &[Delivery]) -> HashMap<String, String> {
+    let mut counts: HashMap<&str, (usize, usize)> = HashMap::new();
+    for delivery in deliveries {
+        for fullname in &delivery.posts {
+            let count = counts.entry(fullname.as_str()).or_insert((0, 0));
+            count.0 += delivery.sent().len();
+            count.1 += delivery.failures().len();
+        }
+    }
+    counts.into_iter()
+        .map(|(fullname, (sent, failed))| (fullname.to_string(), match failed {
+            0 => format!("sent to {} recipient(s)", sent),
+            _ => format!("sent to {} recipient(s), failed for {} (will retry)", sent, failed),
+        }))
+        .collect()
+}
+
+fn _report_failures(deliveries: &[Delivery]) {
+    deliveries.iter()
+        .flat_map(|d| d.failures().into_iter().map(move |(channel, e)| (d, channel, e)))
+        .for_each(|(d, channel, e)| eprintln!("Could not deliver {} post(s) from {} ({}) to channel {}: {:?}", d.posts.len(), d.subreddit, d.posts.join(", "), channel, e));
+}
+
+#[cfg(test)]
+mod monitor_tests {
+    use crate::sinks::Undelivered;
+
+    use super::*;
+
+    /// What a sink was sent, as (thread posts?, keys).
+    type Sent = Arc<Mutex<Vec<(bool, Vec<String>)>>>;
+    struct Recorder(Sent);
+    impl Sink for Recorder {
+        fn notifier(&self) -> Notifier {
+            Notifier::Exec
+        }
+        fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+            self.0.lock().unwrap().push((false, posts.keys().cloned().collect()));
+            Ok(())
+        }
+        fn send_threads(&self, threads: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+            self.0.lock().unwrap().push((true, threads.keys().cloned().collect()));
+            Ok(())
+        }
+    }
+
+    /// A sink that never gets anything through.
+    struct Broken;
+    impl Sink for Broken {
+        fn notifier(&self) -> Notifier {
+            Notifier::Slack
+        }
+        fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+            Err(Undelivered::all("broken", posts))
+        }
+    }
+
+    #[test]
+    fn failed_posts_are_given_up_on() {
+        let config = Arc::new(Mutex::new(Config::example(&["rust"])));
+        let sinks: Mutex<Vec<Box<dyn Sink>>> = Mutex::new(vec![Box::new(Broken)]);
+        let state = Mutex::new(State::default());
+
+        let mut new_posts = HashMap::new();
+        new_posts.insert("r/rust".to_string(), vec![Post::example("post")]);
+        let (_, statuses, dropped) = _notify_sinks(&sinks, &config, &state, &new_posts, &HashMap::new(), 0);
+        assert_eq!(statuses["r/rust"]["Slack"], "failed: broken");
+        assert!(dropped.is_empty());
+        for _ in 2..digest::MAX_ATTEMPTS {
+            assert!(_notify_sinks(&sinks, &config, &state, &HashMap::new(), &HashMap::new(), 0).2.is_empty());
+        }
+        assert_eq!(state.lock().unwrap().digests.held(), 1);
+
+        let (_, _, dropped) = _notify_sinks(&sinks, &config, &state, &HashMap::new(), &HashMap::new(), 0);
+        assert_eq!(state.lock().unwrap().digests.held(), 0);
+        let (config, none, subscribed) = (config.lock().unwrap(), HashMap::new(), HashMap::new());
+        let entries = _log_entries(&config, &none, &none, &subscribed, &SinkStatuses::new(), &dropped, &[]);
+        assert_eq!((entries[0].subreddit, entries[0].post.fullname.as_str()), ("r/rust", "t3_post"));
+        assert_eq!(entries[0].delivery["Slack"], format!("dropped after {} failed attempts", digest::MAX_ATTEMPTS));
+    }
+    #[test]
+    fn thread_posts_stay_apart() {
+        let config = Arc::new(Mutex::new(Config::example(&["rust"])));
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let sinks: Mutex<Vec<Box<dyn Sink>>> = Mutex::new(vec![Box::new(Recorder(Arc::clone(&recorded)))]);
+
+        let mut new_posts = HashMap::new();
+        new_posts.insert("r/rust".to_string(), vec![Post::example("post")]);
+        // thread posts are keyed by the thread's permalink, which starts just like a subreddit
+        let mut thread_posts = HashMap::new();
+        thread_posts.insert("r/rust/comments/abc/title".to_string(), vec![Post { fullname: "t1_comment".to_string(), ..Post::example("abc") }]);
+
+        let ((posts, threads), statuses, _) = _notify_sinks(&sinks, &config, &Mutex::new(State::default()), &new_posts, &thread_posts, 0);
+        assert_eq!(posts.keys().collect::<Vec<&String>>(), vec!["r/rust"]);
+        assert_eq!(threads.keys().collect::<Vec<&String>>(), vec!["r/rust/comments/abc/title"]);
+        assert_eq!(*recorded.lock().unwrap(), vec![
+            (false, vec!["r/rust".to_string()]),
+            (true, vec!["r/rust/comments/abc/title".to_string()]),
+        ]);
+        assert_eq!(statuses["r/rust/comments/abc/title"]["Exec"], "sent");
+    }
+}
@@ -0,0 +1,75 @@
+//! New posts wait in the outbox until Discord confirms that each recipient got them, so nothing is
+//! lost when a send fails (the search cursor has already moved past them by then). The outbox is
+//! part of the saved state, so it survives restarts too.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::reddit::Post;
+
+/// Posts still owed to each recipient: channel id -> subreddit -> posts.
+pub type Pending = HashMap<u64, HashMap<String, Vec<Post>>>;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Outbox(Pending);
+impl Outbox {
+    /// Queues posts (keyed by subreddit) for every recipient.
+    pub fn push(&mut self, recipients: &[u64], posts: &HashMap<String, Vec<Post>>) {
+        if posts.is_empty() {
+            return;
+        }
+        for recipient in recipients {
+            let owed = self.0.entry(*recipient).or_insert_with(HashMap::new);
+            for (sub, list) in posts {
+                let queued = owed.entry(sub.clone()).or_insert_with(Vec::new);
+                for p in list {
+                    if !queued.iter().any(|q| q.fullname == p.fullname) {
+                        queued.push(p.clone());
+                    }
+                }
+            }
+        }
+    }
+    pub fn pending(&self) -> &Pending {
+        &self.0
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Marks posts as delivered to a recipient, dropping them from the outbox.
+    pub fn confirm(&mut self, recipient: u64, fullnames: &[String]) {
+        if let Some(owed) = self.0.get_mut(&recipient) {
+            owed.values_mut().for_each(|list| list.retain(|p| !fullnames.contains(&p.fullname)));
+            owed.retain(|_, list| !list.is_empty());
+        }
+        self.0.retain(|_, owed| !owed.is_empty());
+    }
+    /// Forgets everything owed to people who aren't recipients anymore.
+    pub fn retain_recipients(&mut self, recipients: &[u64]) {
+        self.0.retain(|recipient, _| recipients.contains(recipient));
+    }
+}
+
+#[cfg(test)]
+mod outbox_tests {
+    use super::*;
+
+    #[test]
+    fn posts_stay_until_confirmed_per_recipient() {
+        let mut outbox = Outbox::default();
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![Post::example("a"), Post::example("b")]);
+        outbox.push(&[1, 2], &posts);
+        outbox.push(&[1, 2], &posts);
+        assert_eq!(outbox.pending()[&1]["r/rust"].len(), 2);
+
+        outbox.confirm(1, &["t3_a".to_string(), "t3_b".to_string()]);
+        outbox.confirm(2, &["t3_a".to_string()]);
+        assert!(!outbox.pending().contains_key(&1));
+        assert_eq!(outbox.pending()[&2]["r/rust"].iter().map(|p| p.fullname.as_str()).collect::<Vec<_>>(), vec!["t3_b"]);
+
+        outbox.retain_recipients(&[1]);
+        assert!(outbox.is_empty());
+    }
+}
@@ -1,27 +1,166 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use chrono::Utc;
+use regex::Regex;
 use reqwest::Client;
 use reqwest::header;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::config::Config;
+use crate::config::{Config, Priority, PriorityRule, SubredditData};
+use crate::utils::truncate;
 use self::listing::*;
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Post {
+    pub fullname: String,
     pub title: String,
-    pub link: Url,
+    pub author: String,
+    pub subreddit: String, // with the prefix, e.g. r/rust
+    #[serde(with = "url_serde")]
+    pub link: Url, // where the post points to, which is the permalink for self posts
+    #[serde(with = "url_serde")]
+    pub permalink: Url,
+    pub domain: String,
+    pub flair: Option<String>,
+    pub nsfw: bool,
+    pub spoiler: bool,
+    #[serde(with = "url_serde")]
+    pub thumbnail: Option<Url>,
+    #[serde(with = "url_serde")]
+    pub image: Option<Url>, // the full-sized preview image, if Reddit made one
+    pub selftext: String, // only an excerpt, see `SELFTEXT_EXCERPT`
     pub score: i64,
     pub comments: u64,
-    pub posted: Duration,
+    pub created_utc: i64,
+    pub posted: Duration, // age of the post when it was found
+    #[serde(default)]
+    pub removal: Option<Removal>,
+    #[serde(default)]
+    pub priority: Priority, // set by the subreddit's priority rules
+}
+impl Post {
+    fn from_link(link: &Link) -> Post {
+        Post {
+            fullname: link.name.clone(),
+            title: link.title.clone(),
+            author: link.author.clone(),
+            subreddit: link.subreddit_name_prefixed.clone(),
+            link: link.url.clone(),
+            permalink: reddit_url(&link.permalink),
+            domain: link.domain.clone(),
+            flair: link.link_flair_text.clone().filter(|f| !f.is_empty()),
+            nsfw: link.over_18,
+            spoiler: link.spoiler,
+            thumbnail: Url::parse(&link.thumbnail).ok(), // "self", "default", "nsfw", etc. aren't urls
+            image: link.preview_image(),
+            selftext: truncate(&link.selftext, SELFTEXT_EXCERPT),
+            score: link.score,
+            comments: link.num_comments,
+            created_utc: link.created_utc as i64,
+            posted: Duration::from_secs((Utc::now().timestamp() as u64).saturating_sub(link.created_utc as u64)),
+            removal: link.removal(),
+            priority: Priority::Normal,
+        }
+    }
+}
+#[cfg(test)]
+impl Post {
+    /// A plain post (`t3_<id>` in r/rust, linking to example.com) for tests, which set whatever else
+    /// they care about with struct update syntax.
+    pub(crate) fn example(id: &str) -> Post {
+        Post {
+            fullname: format!("t3_{}", id),
+            title: "Post".to_string(),
+            author: "ferris".to_string(),
+            subreddit: "r/rust".to_string(),
+            link: Url::parse("https://example.com/").unwrap(),
+            permalink: Url::parse(&format!("https://old.reddit.com/r/rust/comments/{}/", id)).unwrap(),
+            domain: "example.com".to_string(),
+            flair: None,
+            nsfw: false,
+            spoiler: false,
+            thumbnail: None,
+            image: None,
+            selftext: String::new(),
+            score: 3,
+            comments: 1,
+            created_utc: 0,
+            posted: Duration::from_secs(60),
+            removal: None,
+            priority: Priority::Normal,
+        }
+    }
+}
+
+/// How many characters of a post's selftext are kept around.
+pub const SELFTEXT_EXCERPT: usize = 300;
+
+/// Compiles a pattern from the config, or hands back the copy compiled on an earlier check.
+fn _compiled(patterns: &mut HashMap<String, Regex>, pattern: &str) -> Result<Regex, regex::Error> {
+    if let Some(re) = patterns.get(pattern) {
+        return Ok(re.clone());
+    }
+    let re = Regex::new(pattern)?;
+    patterns.insert(pattern.to_string(), re.clone());
+    Ok(re)
+}
+
+/// Compiles a subreddit's priority rules, leaving out (and reporting) any that aren't valid regexes,
+/// though `Config::validate` already turns those away when the config is read.
+fn _priority_rules(patterns: &mut HashMap<String, Regex>, rules: &[PriorityRule], sub: &str) -> Vec<(Regex, Priority)> {
+    rules.iter()
+        .filter_map(|rule| match _compiled(patterns, &rule.matches) {
+            Ok(re) => Some((re, rule.priority)),
+            Err(e) => { eprintln!("Invalid priority rule for r/{}: {}", sub, e); None },
+        })
+        .collect()
+}
+
+/// The priority of the first rule matching the post's title or flair.
+fn _priority(rules: &[(Regex, Priority)], post: &Post) -> Priority {
+    rules.iter()
+        .find(|(re, _)| re.is_match(&post.title) || post.flair.as_ref().map_or(false, |f| re.is_match(f)))
+        .map_or(Priority::Normal, |(_, priority)| *priority)
+}
+
+/// Turns a path from the API (like a permalink) into a full link to old Reddit.
+fn reddit_url(path: &str) -> Url {
+    lazy_static! {
+        static ref OLD_REDDIT: Url = Url::parse("https://old.reddit.com").unwrap();
+    }
+    OLD_REDDIT.join(path).unwrap_or_else(|_| OLD_REDDIT.clone())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Removal {
+    Deleted, // by the author
+    Removed(String), // by the mods, the admins, automod, etc; holds Reddit's `removed_by_category`
+}
+impl Display for Removal {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Removal::Deleted => write!(f, "was deleted by its author"),
+            Removal::Removed(category) => write!(f, "was removed ({})", category.replace('_', " ")),
+        }
+    }
+}
+
+/// Everything found in a watched thread on one check.
+#[derive(Debug)]
+pub struct ThreadComments {
+    pub permalink: String, // of the thread, without the surrounding slashes
+    pub comments: Vec<(Post, bool)>, // each comment, and whether it passes the watch's filters
 }
 
 pub struct Redditor {
     client: Client,
     config: Arc<Mutex<Config>>,
+    patterns: HashMap<String, Regex>, // compiled priority rules and thread filters, by their pattern
     pub token: OAuthToken
 }
 impl Redditor {
@@ -42,6 +181,7 @@ impl Redditor {
         let mut r = Redditor {
             client,
             config,
+            patterns: HashMap::new(),
             token: OAuthToken::new("", 0)
         };
         r._update_token()?;
@@ -53,9 +193,29 @@ impl Redditor {
         if self.token.is_expired() {
             self._update_token().expect("Failed to update token before running `check`");
         }
-        let mut config = self.config.lock().expect("Arc lock was poisoned in the config");
-        let results: HashMap<String, reqwest::Result<reqwest::Response>> = config.subreddit_configs.iter().map(|sub| {
-            (sub.subreddit.clone(), self.client
+        let config = Arc::clone(&self.config);
+        let mut config = config.lock().expect("Arc lock was poisoned in the config");
+        self._search(&mut config.subreddit_configs)
+    }
+    /// Runs every user's own searches, returning the new posts keyed by user id and then subreddit.
+    pub fn check_subscriptions(&mut self) -> HashMap<u64, HashMap<String, Vec<Post>>> {
+        if self.token.is_expired() {
+            self._update_token().expect("Failed to update token before running `check_subscriptions`");
+        }
+        let config = Arc::clone(&self.config);
+        let mut config = config.lock().expect("Arc lock was poisoned in the config");
+        config.subscriptions.iter_mut()
+            .map(|(user, subs)| (*user, self._search(subs)))
+            .filter(|(_, posts)| !posts.is_empty())
+            .collect()
+    }
+    /// Searches each (unpaused) subreddit for posts newer than the last one seen, moving each
+    /// search's cursor up to the newest post found.
+    fn _search(&mut self, subreddit_configs: &mut Vec<SubredditData>) -> HashMap<String, Vec<Post>> {
+        // a subreddit that can't be searched (e.g. it's private, banned or doesn't exist) is only
+        // skipped, and its cursor stays where it was
+        let results: HashMap<String, Thing<Listing>> = subreddit_configs.iter().filter(|sub| !sub.paused).filter_map(|sub| {
+            let response: reqwest::Result<Thing<Listing>> = self.client
                 .get(format!("https://oauth.reddit.com/r/{}/search", sub.subreddit).as_str())
                 .bearer_auth(&self.token.token)
                 .query(&[
@@ -67,13 +227,14 @@ impl Redditor {
                     ("sort", &sub.search_query.sort),
                     ("t", &sub.search_query.t),
                 ])
-                .send())
+                .send()
+                .and_then(|r| r.error_for_status())
+                .and_then(|mut r| r.json());
+            match response {
+                Ok(listing_thing) => Some((sub.subreddit.clone(), listing_thing)),
+                Err(e) => { eprintln!("Could not search r/{}: {}", sub.subreddit, e); None },
+            }
         }).collect();
-        if results.len() == 0 || results.values().any(|v| v.is_err()) {
-            return HashMap::new();
-        }
-
-        let results: HashMap<String, Thing<Listing>> = results.into_iter().map(|(k, v): (String, reqwest::Result<reqwest::Response>)| (k, v.unwrap().json().expect("Failed to parse JSON for reqwest response"))).collect();
 
         results.into_iter()
             .filter(|(_, listing_thing)| !listing_thing.children.is_empty())
@@ -81,21 +242,102 @@ impl Redditor {
                 (format!("r/{}", sub), {
                     // first we need to update the subreddit config to be aware of the latest seen post
                     let latest = listing_thing.children.first().expect("For some reason, the children array was empty").name.clone();
-                    config.subreddit_configs.iter_mut().find(|c| c.subreddit == sub).unwrap().search_query.before = latest;
-
-                    listing_thing.children.iter().map(|post| {
-                        Post {
-                            title: post.title.clone(),
-                            link: post.url.clone(),
-                            score: post.score,
-                            comments: post.num_comments,
-                            posted: Duration::from_secs((Utc::now().timestamp() as u64) - (post.created_utc as u64))
-                        }
-                    }).collect::<Vec<Post>>()
+                    let sub_config = subreddit_configs.iter_mut().find(|c| c.subreddit == sub).unwrap();
+                    sub_config.search_query.before = latest;
+
+                    let rules = _priority_rules(&mut self.patterns, &sub_config.priorities, &sub);
+                    listing_thing.children.iter()
+                        .map(|post| Post::from_link(post))
+                        .map(|post| Post { priority: _priority(&rules, &post), ..post })
+                        .collect::<Vec<Post>>()
                 })
             }).collect()
     }
 
+    /// Polls the comment trees of every watched thread, handing back all of their comments along
+    /// with whether each one passes the watch's filters, keyed by the thread's id. Which of them are
+    /// new is up to the thread's progress in the state.
+    pub fn check_threads(&mut self) -> HashMap<String, ThreadComments> {
+        if self.token.is_expired() {
+            self._update_token().expect("Failed to update token before running `check_threads`");
+        }
+        let config = Arc::clone(&self.config);
+        let config = config.lock().expect("Arc lock was poisoned in the config");
+        let now = Utc::now().timestamp();
+
+        let mut results = HashMap::new();
+        for watch in config.thread_watches.iter() {
+            let filter = match _compiled(&mut self.patterns, &watch.filter) {
+                Ok(re) => re,
+                Err(e) => { eprintln!("Invalid filter for thread {}: {}", watch.thread, e); continue; }
+            };
+            let response: reqwest::Result<(Thing<Listing>, Thing<Listing<CommentThing>>)> = self.client
+                .get(format!("https://oauth.reddit.com/comments/{}", watch.thread).as_str())
+                .bearer_auth(&self.token.token)
+                .query(&[("sort", "new"), ("limit", "500")])
+                .send()
+                .and_then(|mut r| r.json());
+            let (thread, comments) = match response {
+                Ok(r) => r,
+                Err(e) => { eprintln!("Could not fetch thread {}: {}", watch.thread, e); continue; }
+            };
+            let (permalink, subreddit) = match thread.children.first() {
+                Some(link) => (link.permalink.trim_matches('/').to_string(), link.subreddit_name_prefixed.clone()),
+                None => continue,
+            };
+
+            let comments = comments.comments().into_iter()
+                .map(|c| {
+                    let wanted = (!watch.top_level_only || c.is_top_level()) && filter.is_match(&c.body);
+                    (Post {
+                        fullname: c.name.clone(),
+                        title: format!("u/{}: {}", c.author, truncate(&c.body, 200)),
+                        author: c.author.clone(),
+                        subreddit: subreddit.clone(),
+                        link: reddit_url(&c.permalink),
+                        permalink: reddit_url(&c.permalink),
+                        domain: "reddit.com".to_string(),
+                        flair: None,
+                        nsfw: false,
+                        spoiler: false,
+                        thumbnail: None,
+                        image: None,
+                        selftext: truncate(&c.body, SELFTEXT_EXCERPT),
+                        score: c.score,
+                        comments: c.reply_count() as u64,
+                        created_utc: c.created_utc as i64,
+                        posted: Duration::from_secs((now as u64).saturating_sub(c.created_utc as u64)),
+                        removal: None,
+                        priority: Priority::Normal,
+                    }, wanted)
+                })
+                .collect();
+            results.insert(watch.thread.clone(), ThreadComments { permalink, comments });
+        }
+
+        results
+    }
+
+    /// Fetches the current versions of the given posts (by fullname), batching the requests since
+    /// `/api/info` only takes 100 names at a time. Posts Reddit no longer knows about are left out.
+    pub fn info(&mut self, fullnames: &[String]) -> reqwest::Result<Vec<Post>> {
+        if self.token.is_expired() {
+            self._update_token()?;
+        }
+        let mut posts = Vec::with_capacity(fullnames.len());
+        for chunk in fullnames.chunks(100) {
+            let listing_thing: Thing<Listing> = self.client
+                .get("https://oauth.reddit.com/api/info")
+                .bearer_auth(&self.token.token)
+                .query(&[("id", chunk.join(","))])
+                .send()?
+                .json()?;
+            posts.extend(listing_thing.children.iter().map(|post| Post::from_link(post)));
+        }
+
+        Ok(posts)
+    }
+
     fn _update_token(&mut self) -> reqwest::Result<()> {
         let config = self.config.lock().expect("Arc lock was poisoned in the config");
         let resp: AuthResponse = self.client
@@ -153,6 +395,8 @@ mod listing {
     use serde::Deserialize;
     use url::Url;
 
+    use super::Removal;
+
     #[derive(Debug, Deserialize)]
     pub struct Thing<T> {
         pub data: T,
@@ -167,22 +411,129 @@ mod listing {
     }
 
     #[derive(Debug, Deserialize)]
-    pub struct Listing {
-        pub children: Vec<Thing<Link>>,
-        pub dist: u8,
+    pub struct Listing<T = Thing<Link>> {
+        pub children: Vec<T>,
+        pub dist: Option<u8>,
+    }
+    impl Listing<CommentThing> {
+        /// Flattens the comment tree depth-first. Unexpanded "more" stubs are skipped, so comments
+        /// Reddit left out of the response (deep or busy branches) are never seen.
+        pub fn comments(&self) -> Vec<&Comment> {
+            let mut out = Vec::new();
+            self.children.iter().for_each(|t| if let CommentThing::Comment(c) = t {
+                out.push(c);
+                if let Replies::Listing(ref l) = c.replies {
+                    out.extend(l.comments());
+                }
+            });
+            out
+        }
     }
 
     #[derive(Debug, Deserialize)]
     pub struct Link {
+        pub author: String,
         pub created_utc: f64,
+        pub domain: String,
+        pub link_flair_text: Option<String>,
         pub name: String,
         pub num_comments: u64,
+        pub over_18: bool,
+        pub permalink: String,
+        pub preview: Option<Preview>,
+        pub removed_by_category: Option<String>,
         pub score: i64,
         pub selftext: String,
+        pub spoiler: bool,
         pub subreddit_name_prefixed: String,
+        pub thumbnail: String,
         pub title: String,
         #[serde(with = "url_serde")] pub url: Url,
     }
+    impl Link {
+        /// The source of the first preview image. The API html-escapes these unless asked for raw json.
+        pub fn preview_image(&self) -> Option<Url> {
+            let image = self.preview.as_ref()?.images.first()?;
+            Url::parse(&image.source.url.replace("&amp;", "&")).ok()
+        }
+        /// Works out whether the post is gone, and who took it down.
+        pub fn removal(&self) -> Option<Removal> {
+            match self.removed_by_category.as_ref().map(String::as_str) {
+                Some("deleted") | Some("author") => Some(Removal::Deleted),
+                Some(category) => Some(Removal::Removed(category.to_string())),
+                None if self.author == "[deleted]" || self.selftext == "[deleted]" => Some(Removal::Deleted),
+                None if self.selftext == "[removed]" => Some(Removal::Removed("moderator".to_string())),
+                None => None,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Preview {
+        pub images: Vec<PreviewImage>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct PreviewImage {
+        pub source: ImageSource,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ImageSource {
+        pub url: String,
+        pub width: u32,
+        pub height: u32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(tag = "kind", content = "data")]
+    pub enum CommentThing {
+        #[serde(rename = "t1")] Comment(Comment),
+        #[serde(rename = "more")] More(More),
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Comment {
+        pub author: String,
+        pub body: String,
+        pub created_utc: f64,
+        pub name: String,
+        pub parent_id: String,
+        pub permalink: String,
+        #[serde(default)]
+        pub replies: Replies,
+        pub score: i64,
+    }
+    impl Comment {
+        pub fn is_top_level(&self) -> bool {
+            self.parent_id.starts_with("t3_")
+        }
+        pub fn reply_count(&self) -> usize {
+            match self.replies {
+                Replies::Listing(ref l) => l.children.iter().filter(|t| match t { CommentThing::Comment(_) => true, _ => false }).count(),
+                Replies::Empty(_) => 0,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct More {
+        pub children: Vec<String>,
+        pub count: u64,
+    }
+
+    /// Reddit sends an empty string rather than an empty listing when a comment has no replies.
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    pub enum Replies {
+        Listing(Thing<Listing<CommentThing>>),
+        Empty(String),
+    }
+    impl Default for Replies {
+        fn default() -> Self {
+            Replies::Empty(String::new())
+        }
+    }
 }
 
 #[cfg(test)]
@@ -199,4 +550,67 @@ mod reddit_tests {
 
         assert!(r.is_ok());
     }
+
+    #[test]
+    fn priority_rules() {
+        let rules = vec![
+            PriorityRule { matches: "(?i)urgent".to_string(), priority: Priority::Urgent },
+            PriorityRule { matches: "[".to_string(), priority: Priority::Urgent },
+            PriorityRule { matches: "^Meta$".to_string(), priority: Priority::Low },
+        ];
+        let mut patterns = HashMap::new();
+        let rules = _priority_rules(&mut patterns, &rules, "rust");
+        assert_eq!(rules.len(), 2);
+        assert_eq!(patterns.len(), 2);
+
+        let mut link: Link = serde_json::from_str(r#"{"name": "t3_a", "title": "URGENT: help", "author": "a",
+            "subreddit_name_prefixed": "r/rust", "url": "https://example.com/", "permalink": "/r/rust/comments/a/",
+            "domain": "example.com", "link_flair_text": null, "over_18": false, "spoiler": false, "thumbnail": "self",
+            "selftext": "", "score": 1, "num_comments": 0, "created_utc": 0.0}"#).unwrap();
+        assert_eq!(_priority(&rules, &Post::from_link(&link)), Priority::Urgent);
+        link.title = "Something else".to_string();
+        assert_eq!(_priority(&rules, &Post::from_link(&link)), Priority::Normal);
+        link.link_flair_text = Some("Meta".to_string());
+        assert_eq!(_priority(&rules, &Post::from_link(&link)), Priority::Low);
+    }
+
+    #[test]
+    fn comment_tree() {
+        let json = r#"{"kind": "Listing", "data": {"dist": null, "children": [
+            {"kind": "t1", "data": {"author": "a", "body": "top", "created_utc": 0.0, "name": "t1_a",
+                "parent_id": "t3_x", "permalink": "/r/rust/comments/x/t/a/", "score": 3, "replies":
+                {"kind": "Listing", "data": {"dist": null, "children": [
+                    {"kind": "t1", "data": {"author": "b", "body": "reply", "created_utc": 0.0, "name": "t1_b",
+                        "parent_id": "t1_a", "permalink": "/r/rust/comments/x/t/b/", "score": 1, "replies": ""}},
+                    {"kind": "more", "data": {"children": ["c"], "count": 1}}
+                ]}}}},
+            {"kind": "t1", "data": {"author": "d", "body": "second", "created_utc": 0.0, "name": "t1_d",
+                "parent_id": "t3_x", "permalink": "/r/rust/comments/x/t/d/", "score": 0, "replies": ""}}
+        ]}}"#;
+        let listing: Thing<Listing<CommentThing>> = serde_json::from_str(json).unwrap();
+        let comments = listing.comments();
+
+        assert_eq!(comments.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["t1_a", "t1_b", "t1_d"]);
+        assert!(comments[0].is_top_level() && !comments[1].is_top_level());
+        assert_eq!(comments[0].reply_count(), 1);
+    }
+
+    #[test]
+    fn link_to_post() {
+        let json = r#"{"author": "ferris", "created_utc": 1558000000.0, "domain": "i.redd.it",
+            "link_flair_text": "Help", "name": "t3_abc", "num_comments": 4, "over_18": false,
+            "permalink": "/r/rust/comments/abc/a_title/", "preview": {"images": [{"source":
+                {"url": "https://preview.redd.it/x.png?width=640&amp;s=1", "width": 640, "height": 480}}]},
+            "removed_by_category": null, "score": 12, "selftext": "", "spoiler": true,
+            "subreddit_name_prefixed": "r/rust", "thumbnail": "https://b.thumbs.redditmedia.com/x.jpg",
+            "title": "A title", "url": "https://i.redd.it/x.png"}"#;
+        let link: Link = serde_json::from_str(json).unwrap();
+        let post = Post::from_link(&link);
+
+        assert_eq!(post.permalink.as_str(), "https://old.reddit.com/r/rust/comments/abc/a_title/");
+        assert_eq!(post.image.unwrap().as_str(), "https://preview.redd.it/x.png?width=640&s=1");
+        assert_eq!(post.flair, Some("Help".to_string()));
+        assert_eq!(post.created_utc, 1558000000);
+        assert!(post.spoiler && !post.nsfw && post.thumbnail.is_some() && post.removal.is_none());
+    }
 }
@@ -0,0 +1,273 @@
+//! Keeps Atom feeds of the latest posts: one for every subreddit config (e.g. `rust.xml`) plus
+//! `all.xml` with everything. They can be written to a directory after every check, served over
+//! HTTP, or both. When written to a directory, the posts behind them are saved there too, so the
+//! feeds pick up where they left off after a restart.
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use chrono::{TimeZone, Utc};
+use tiny_http::{Header, Response, Server};
+
+use crate::config::{Config, Feeds, Notifier};
+use crate::reddit::Post;
+use crate::sinks::{Sink, Undelivered};
+use crate::utils::escape_html;
+
+/// How many posts each feed keeps unless the config says otherwise.
+const DEFAULT_ENTRIES: usize = 50;
+const AGGREGATE: &str = "all";
+/// How often the HTTP server looks up from waiting on requests to see if it should stop.
+const SERVER_POLL: Duration = Duration::from_millis(200);
+/// How long the HTTP server waits for its address to be let go of, e.g. by the sink it replaces on a
+/// reload, whose server closes its socket a moment after being stopped.
+const BIND_PATIENCE: Duration = Duration::from_secs(2);
+
+type Entries = HashMap<String, VecDeque<Post>>; // feed name -> its newest posts, newest first
+
+pub struct AtomSink {
+    directory: Option<PathBuf>,
+    size: usize,
+    config: Arc<Mutex<Config>>,
+    entries: Mutex<Entries>,
+    rendered: Arc<Mutex<HashMap<String, String>>>, // file name -> feed, for the HTTP server
+    server: Option<(Arc<AtomicBool>, JoinHandle<()>)>, // set to stop serving
+}
+impl AtomSink {
+    pub fn new(feeds: &Feeds, config: Arc<Mutex<Config>>) -> Result<AtomSink, Box<dyn Error>> {
+        let mut entries: Entries = match feeds.directory {
+            Some(ref dir) if _entries_path(dir).exists() => ron::de::from_str(&fs::read_to_string(_entries_path(dir))?)?,
+            _ => Entries::new(),
+        };
+        // older versions saved thread posts under feeds named after the thread, which can't be files
+        entries.retain(|name, _| _is_feed_name(name));
+        // the feeds that already have posts are rendered straight away, the rest on the first check
+        let mut names: Vec<String> = entries.keys().cloned().collect();
+        names.push(AGGREGATE.to_string());
+        let mut sink = AtomSink {
+            directory: feeds.directory.clone(),
+            size: feeds.entries.unwrap_or(DEFAULT_ENTRIES),
+            config,
+            entries: Mutex::new(entries),
+            rendered: Arc::new(Mutex::new(HashMap::new())),
+            server: None,
+        };
+        sink._render_feeds(names)?;
+
+        if let Some(ref address) = feeds.listen {
+            let server = _bind(address)?;
+            let rendered = Arc::clone(&sink.rendered);
+            let stop = Arc::new(AtomicBool::new(false));
+            let stopped = Arc::clone(&stop);
+            sink.server = Some((stop, thread::spawn(move || {
+                while !stopped.load(Ordering::SeqCst) {
+                    let request = match server.recv_timeout(SERVER_POLL) {
+                        Ok(Some(request)) => request,
+                        Ok(None) => continue,
+                        Err(e) => { eprintln!("Could not serve a feed: {}", e); continue },
+                    };
+                    let feed = rendered.lock().expect("Mutex was poisoned in the feeds").get(request.url().trim_start_matches('/')).cloned();
+                    let response = match feed {
+                        Some(feed) => Response::from_string(feed)
+                            .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/atom+xml; charset=utf-8"[..]).unwrap()),
+                        None => Response::from_string("No such feed").with_status_code(404),
+                    };
+                    if let Err(e) = request.respond(response) {
+                        eprintln!("Could not serve a feed: {}", e);
+                    }
+                }
+            })));
+        }
+
+        Ok(sink)
+    }
+    /// Puts posts at the top of a feed, dropping the oldest ones past its size.
+    fn _add(&self, name: &str, posts: &[Post]) {
+        let mut entries = self.entries.lock().expect("Mutex was poisoned in the feeds");
+        let feed = entries.entry(name.to_string()).or_default();
+        posts.iter().rev().for_each(|p| feed.push_front(p.clone()));
+        feed.truncate(self.size);
+    }
+    /// Renders every feed, writing them out and serving them if asked to.
+    fn _render(&self) -> Result<(), Box<dyn Error>> {
+        let mut names: Vec<String> = self.config.lock().expect("Arc lock was poisoned in the config")
+            .subreddit_configs.iter().map(|c| c.subreddit.clone()).filter(|name| _is_feed_name(name)).collect();
+        names.push(AGGREGATE.to_string());
+        self._render_feeds(names)
+    }
+    fn _render_feeds(&self, names: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let entries = self.entries.lock().expect("Mutex was poisoned in the feeds");
+
+        let empty = VecDeque::new();
+        let feeds: HashMap<String, String> = names.iter()
+            .map(|name| (format!("{}.xml", name), _feed(name, entries.get(name).unwrap_or(&empty))))
+            .collect();
+
+        if let Some(ref dir) = self.directory {
+            fs::create_dir_all(dir)?;
+            for (file, feed) in &feeds {
+                fs::write(dir.join(file), feed)?;
+            }
+            fs::write(_entries_path(dir), ron::ser::to_string(&*entries)?)?;
+        }
+        *self.rendered.lock().expect("Mutex was poisoned in the feeds") = feeds;
+        Ok(())
+    }
+}
+impl Sink for AtomSink {
+    fn notifier(&self) -> Notifier {
+        Notifier::Feed
+    }
+    /// The posts are kept in the feeds' entries even if they can't be written out, and go out with
+    /// the next ones, so there's nothing to try again.
+    fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        for (sub, list) in posts {
+            let name = sub.trim_start_matches("r/");
+            if _is_feed_name(name) {
+                self._add(name, list);
+            }
+            self._add(AGGREGATE, list);
+        }
+        self._render().map_err(Undelivered::none)
+    }
+    /// Thread posts only go in the aggregate feed.
+    fn send_threads(&self, threads: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        for list in threads.values() {
+            self._add(AGGREGATE, list);
+        }
+        self._render().map_err(Undelivered::none)
+    }
+}
+/// Stops serving the feeds, letting go of the address so a reloaded config can use it again.
+impl Drop for AtomSink {
+    fn drop(&mut self) {
+        if let Some((stop, handle)) = self.server.take() {
+            stop.store(true, Ordering::SeqCst);
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Whether a name can be used for a feed, i.e. as a file name in the feeds' directory.
+fn _is_feed_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(&['/', '\\'][..])
+}
+
+/// Starts an HTTP server on the address, trying again for a little while if it's still in use.
+fn _bind(address: &str) -> Result<Server, Box<dyn Error>> {
+    let started = Instant::now();
+    loop {
+        match Server::http(address) {
+            Ok(server) => return Ok(server),
+            Err(e) => {
+                let in_use = e.downcast_ref::<io::Error>().map_or(false, |e| e.kind() == io::ErrorKind::AddrInUse);
+                if !in_use || started.elapsed() >= BIND_PATIENCE {
+                    return Err(e);
+                }
+            },
+        }
+        thread::sleep(SERVER_POLL / 4);
+    }
+}
+
+fn _entries_path(dir: &PathBuf) -> PathBuf {
+    dir.join("entries.ron")
+}
+
+fn _timestamp(utc: i64) -> String {
+    Utc.timestamp_opt(utc, 0).single().unwrap_or_else(Utc::now).to_rfc3339()
+}
+
+/// Renders an Atom feed, see https://tools.ietf.org/html/rfc4287
+fn _feed(name: &str, posts: &VecDeque<Post>) -> String {
+    let (title, link) = if name == AGGREGATE {
+        ("All matching posts".to_string(), "https://old.reddit.com/".to_string())
+    } else {
+        (format!("Matching posts in r/{}", name), format!("https://old.reddit.com/r/{}", name))
+    };
+    let updated = posts.iter().map(|p| p.created_utc).max().map_or_else(|| Utc::now().to_rfc3339(), _timestamp);
+
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <title>{}</title>\n<id>urn:{}:{}</id>\n<link href=\"{}\"/>\n<updated>{}</updated>\n<generator>{}</generator>\n",
+        escape_html(&title), env!("CARGO_PKG_NAME"), escape_html(name), escape_html(&link), updated, env!("CARGO_PKG_NAME"),
+    );
+    for p in posts {
+        feed.push_str(&format!(
+            "<entry>\n<title>{}</title>\n<id>{}</id>\n<link href=\"{}\"/>\n<updated>{}</updated>\n<author><name>u/{}</name></author>\n",
+            escape_html(&p.title), escape_html(p.permalink.as_str()), escape_html(p.permalink.as_str()),
+            _timestamp(p.created_utc), escape_html(&p.author),
+        ));
+        if let Some(ref flair) = p.flair {
+            feed.push_str(&format!("<category term=\"{}\"/>\n", escape_html(flair)));
+        }
+        let summary = if p.selftext.is_empty() { format!("{} · {}", p.subreddit, p.link) } else { p.selftext.clone() };
+        feed.push_str(&format!("<summary>{}</summary>\n</entry>\n", escape_html(&summary)));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+#[cfg(test)]
+mod atom_tests {
+    use std::env;
+
+    use super::*;
+
+    fn post(n: i64) -> Post {
+        Post { title: format!("Post <#{}>", n), created_utc: 1558000000 + n, ..Post::example(&n.to_string()) }
+    }
+
+    #[test]
+    fn writes_a_feed_per_subreddit_and_one_for_everything() {
+        let dir = env::temp_dir().join(format!("{}-atom-test", env!("CARGO_PKG_NAME")));
+        let _ = fs::remove_dir_all(&dir);
+        let config = Arc::new(Mutex::new(Config::example(&["rust", "python"])));
+        let feeds = Feeds { directory: Some(dir.clone()), listen: None, entries: Some(2) };
+
+        let sink = AtomSink::new(&feeds, Arc::clone(&config)).unwrap();
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![post(3), post(2), post(1)]);
+        sink.send(&posts).unwrap();
+
+        let rust = fs::read_to_string(dir.join("rust.xml")).unwrap();
+        assert_eq!(rust.matches("<entry>").count(), 2);
+        assert!(rust.contains("<title>Post &lt;#3&gt;</title>"));
+        assert!(!rust.contains("Post &lt;#1&gt;"));
+        assert!(fs::read_to_string(dir.join("all.xml")).unwrap().contains("<id>https://old.reddit.com/r/rust/comments/3/</id>"));
+        assert_eq!(fs::read_to_string(dir.join("python.xml")).unwrap().matches("<entry>").count(), 0);
+
+        // a new sink picks up the saved posts
+        let sink = AtomSink::new(&feeds, config).unwrap();
+        assert_eq!(sink.entries.lock().unwrap()["rust"].len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn thread_posts_only_go_in_the_aggregate() {
+        let dir = env::temp_dir().join(format!("{}-atom-thread-test", env!("CARGO_PKG_NAME")));
+        let _ = fs::remove_dir_all(&dir);
+        let config = Arc::new(Mutex::new(Config::example(&[])));
+        let feeds = Feeds { directory: Some(dir.clone()), listen: None, entries: None };
+
+        // saved by older versions, which gave threads feeds of their own
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(_entries_path(&dir), r#"{"rust/comments/abc/title": [], "../escape": []}"#).unwrap();
+        let sink = AtomSink::new(&feeds, config).unwrap();
+        assert!(sink.entries.lock().unwrap().is_empty());
+
+        let mut threads = HashMap::new();
+        threads.insert("r/rust/comments/abc/title".to_string(), vec![post(1)]);
+        sink.send_threads(&threads).unwrap();
+        assert_eq!(sink.entries.lock().unwrap().keys().collect::<Vec<&String>>(), vec![AGGREGATE]);
+        assert!(fs::read_to_string(dir.join("all.xml")).unwrap().contains("<entry>"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
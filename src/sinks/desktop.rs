@@ -0,0 +1,116 @@
+//! Shows new posts as native desktop notifications, through the freedesktop notification spec over
+//! D-Bus (`org.freedesktop.Notifications`). Clicking a notification opens the post with `xdg-open`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::process::Command;
+use std::thread;
+
+use notify_rust::Notification;
+
+use crate::config::{Desktop, Notifier};
+use crate::reddit::Post;
+use crate::sinks::{Missed, Sink, Undelivered};
+use crate::utils::truncate;
+
+/// Past this many posts from one subreddit in a check, the rest are summed up in one notification.
+const MAX_PER_SUBREDDIT: usize = 5;
+const MAX_SUMMARY: usize = 100;
+
+pub struct DesktopSink {
+    timeout: Option<u64>,
+}
+impl DesktopSink {
+    pub fn new(desktop: &Desktop) -> DesktopSink {
+        DesktopSink { timeout: desktop.timeout }
+    }
+    fn _show(&self, n: DesktopNotification) -> Result<(), Box<dyn Error>> {
+        let mut notification = Notification::new();
+        notification.appname(env!("CARGO_PKG_NAME")).summary(&n.summary).body(&n.body);
+        if let Some(timeout) = self.timeout {
+            notification.timeout((timeout * 1000) as i32);
+        }
+        let handle = notification.action("default", "Open").show()?;
+
+        // waiting for a click blocks until the notification is gone, so every one gets a thread
+        let permalink = n.permalink;
+        thread::spawn(move || handle.wait_for_action(|action| {
+            if action == "default" {
+                if let Err(e) = Command::new("xdg-open").arg(&permalink).spawn() {
+                    eprintln!("Could not open {}: {}", permalink, e);
+                }
+            }
+        }));
+        Ok(())
+    }
+}
+impl Sink for DesktopSink {
+    fn notifier(&self) -> Notifier {
+        Notifier::Desktop
+    }
+    fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        // every notification gets its turn even if some fail; only the posts in those are tried again
+        let mut errors = Vec::new();
+        let mut missed = Vec::new();
+        for (sub, list) in posts {
+            for (i, n) in _notifications(sub, list).into_iter().enumerate() {
+                if let Err(e) = self._show(n) {
+                    errors.push(e.to_string());
+                    // past the limit, the last notification sums up the rest of the posts
+                    let shown = if i < MAX_PER_SUBREDDIT { &list[i..=i] } else { &list[MAX_PER_SUBREDDIT..] };
+                    missed.extend(shown.iter().map(|p| Missed { key: sub.clone(), post: p.clone(), to: None }));
+                }
+            }
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(Undelivered { error: From::from(format!("{} notification(s) failed; {}", errors.len(), errors.join("; "))), posts: missed })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct DesktopNotification {
+    summary: String,
+    body: String,
+    permalink: String, // opened when the notification is clicked
+}
+
+/// The notifications for one subreddit's posts.
+fn _notifications(sub: &str, list: &[Post]) -> Vec<DesktopNotification> {
+    let mut notifications = Vec::new();
+    for p in list.iter().take(MAX_PER_SUBREDDIT) {
+        notifications.push(DesktopNotification {
+            summary: truncate(&p.title, MAX_SUMMARY),
+            body: format!("{} · {:+} · {} comment{}", sub, p.score, p.comments, if p.comments == 1 { "" } else { "s" }),
+            permalink: p.permalink.to_string(),
+        });
+    }
+    if list.len() > MAX_PER_SUBREDDIT {
+        notifications.push(DesktopNotification {
+            summary: format!("{} more new post(s) in {}", list.len() - MAX_PER_SUBREDDIT, sub),
+            body: String::new(),
+            permalink: format!("https://old.reddit.com/{}/new", sub),
+        });
+    }
+    notifications
+}
+
+#[cfg(test)]
+mod desktop_tests {
+    use super::*;
+
+    #[test]
+    fn one_notification_per_post_up_to_a_limit() {
+        let posts: Vec<Post> = (0..7).map(|n| Post { title: format!("Post #{}", n), ..Post::example(&n.to_string()) }).collect();
+        let notifications = _notifications("r/rust", &posts);
+
+        assert_eq!(notifications.len(), MAX_PER_SUBREDDIT + 1);
+        assert_eq!(notifications[0], DesktopNotification {
+            summary: "Post #0".to_string(),
+            body: "r/rust · +3 · 1 comment".to_string(),
+            permalink: "https://old.reddit.com/r/rust/comments/0/".to_string(),
+        });
+        assert_eq!(notifications[MAX_PER_SUBREDDIT].summary, "2 more new post(s) in r/rust");
+    }
+}
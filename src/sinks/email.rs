@@ -0,0 +1,172 @@
+//! Emails new posts over SMTP, grouped by subreddit with both HTML and plain text (from the `text`
+//! template) versions. Hourly or daily digests are held back by the monitor, see the `digest` module.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
+
+use crate::config::{Config, Email, EmailSecurity, Notifier};
+use crate::reddit::Post;
+use crate::sinks::{Sink, Undelivered};
+use crate::template::TemplateKind;
+use crate::utils::escape_html;
+
+pub struct EmailSink {
+    email: Email,
+    config: Arc<Mutex<Config>>,
+}
+impl EmailSink {
+    pub fn new(email: &Email, config: Arc<Mutex<Config>>) -> EmailSink {
+        EmailSink { email: email.clone(), config }
+    }
+    fn _client(&self) -> Result<SmtpClient, Box<dyn Error>> {
+        let tls = || -> Result<ClientTlsParameters, Box<dyn Error>> {
+            Ok(ClientTlsParameters::new(self.email.server.clone(), TlsConnector::new()?))
+        };
+        let (security, port) = match self.email.security {
+            EmailSecurity::Tls => (ClientSecurity::Wrapper(tls()?), 465),
+            EmailSecurity::StartTls => (ClientSecurity::Required(tls()?), 587),
+            EmailSecurity::None => (ClientSecurity::None, 25),
+        };
+        let mut client = SmtpClient::new((self.email.server.as_str(), self.email.port.unwrap_or(port)), security)?;
+        if !self.email.username.is_empty() {
+            client = client.credentials(Credentials::new(self.email.username.clone(), self.email.password.clone()));
+        }
+        Ok(client)
+    }
+    fn _send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Box<dyn Error>> {
+        let count: usize = posts.values().map(|list| list.len()).sum();
+        let text = {
+            let config = self.config.lock().expect("Arc lock was poisoned in the config");
+            _text(posts, |sub| config.template(sub, TemplateKind::Text))
+        };
+
+        let mut builder = EmailBuilder::new()
+            .from(self.email.from.as_str())
+            .subject(format!("{} new post(s) on Reddit", count))
+            .alternative(_html(posts), text);
+        for to in &self.email.to {
+            builder = builder.to(to.as_str());
+        }
+
+        self._client()?.transport().send(builder.build()?.into())?;
+        Ok(())
+    }
+}
+impl Sink for EmailSink {
+    fn notifier(&self) -> Notifier {
+        Notifier::Email
+    }
+    /// Everything goes out in one email, so it either all gets through or none of it does.
+    fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        self._send(posts).map_err(|e| Undelivered::all(e, posts))
+    }
+}
+
+fn _sorted(posts: &HashMap<String, Vec<Post>>) -> Vec<(&String, &Vec<Post>)> {
+    let mut sorted: Vec<(&String, &Vec<Post>)> = posts.iter().collect();
+    sorted.sort_by_key(|(sub, _)| sub.as_str());
+    sorted
+}
+
+fn _text<F: Fn(&str) -> crate::template::Template>(posts: &HashMap<String, Vec<Post>>, template: F) -> String {
+    _sorted(posts).into_iter()
+        .map(|(sub, list)| {
+            let t = template(sub);
+            format!("{}\n{}\n", sub, list.iter().map(|p| format!("- {}", t.render(p))).collect::<Vec<String>>().join("\n"))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn _html(posts: &HashMap<String, Vec<Post>>) -> String {
+    let mut html = String::new();
+    for (sub, list) in _sorted(posts) {
+        html.push_str(&format!("<h3><a href=\"https://old.reddit.com/{}\">{}</a></h3><ul>", escape_html(sub), escape_html(sub)));
+        for p in list {
+            html.push_str(&format!(
+                "<li><a href=\"{}\"><b>{}</b></a><br>{:+} · {} comment{} · posted {} ago by u/{}</li>",
+                escape_html(p.permalink.as_str()), escape_html(&p.title), p.score, p.comments,
+                if p.comments == 1 { "" } else { "s" }, humantime::format_duration(p.posted), escape_html(&p.author),
+            ));
+        }
+        html.push_str("</ul>");
+    }
+    html
+}
+
+#[cfg(test)]
+mod email_tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use super::*;
+
+    fn sink(port: u16) -> EmailSink {
+        let email = Email {
+            server: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: EmailSecurity::None,
+            username: String::new(),
+            password: String::new(),
+            from: "buddy@example.com".to_string(),
+            to: vec!["me@example.com".to_string()],
+            digest: Default::default(),
+        };
+        EmailSink::new(&email, Arc::new(Mutex::new(Config::example(&[]))))
+    }
+
+    /// Accepts a single SMTP session, agreeing to everything, and hands back the message's data.
+    fn stand_in_smtp_server() -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut data = String::new();
+            write!(stream, "220 localhost ESMTP\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 { break; }
+                let command = line.to_uppercase();
+                if command.starts_with("DATA") {
+                    write!(stream, "354 go ahead\r\n").unwrap();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line == ".\r\n" { break; }
+                        data.push_str(&line);
+                    }
+                    write!(stream, "250 queued\r\n").unwrap();
+                } else if command.starts_with("QUIT") {
+                    write!(stream, "221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    write!(stream, "250 ok\r\n").unwrap();
+                }
+            }
+            data
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn sends_multipart_emails() {
+        let (port, server) = stand_in_smtp_server();
+        let sink = sink(port);
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![Post { title: "Tips & tricks".to_string(), comments: 2, ..Post::example("abc") }]);
+        sink.send(&posts).unwrap();
+
+        let data = server.join().unwrap();
+        assert!(data.contains("Subject: 1 new post(s) on Reddit"));
+        assert!(data.contains("multipart/alternative"));
+        assert!(data.contains("- Tips & tricks [+3] [2 comments] https://old.reddit.com/r/rust/comments/abc/"));
+        assert!(data.contains("<b>Tips &amp; tricks</b>"));
+    }
+}
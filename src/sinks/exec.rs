@@ -0,0 +1,198 @@
+//! Runs a command for new posts, so the buddy can be glued into shell scripts. The command is run
+//! once per post, or once per check with every post when batching, and gets the posts as `REDDIT_*`
+//! environment variables, as JSON on its stdin, or both.
+//!
+//! Per post, stdin gets the post itself; per batch, it gets an object of subreddit -> list of posts.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{Exec, ExecInput, Notifier};
+use crate::reddit::Post;
+use crate::sinks::{Missed, Sink, Undelivered};
+use crate::utils::truncate;
+
+const DEFAULT_TIMEOUT: u64 = 30;
+/// How much of the command's stderr ends up in the error when it fails.
+const MAX_STDERR: usize = 500;
+
+pub struct ExecSink {
+    command: Vec<String>,
+    batch: bool,
+    input: ExecInput,
+    timeout: Duration,
+}
+impl ExecSink {
+    pub fn new(exec: &Exec) -> Result<ExecSink, Box<dyn Error>> {
+        if exec.command.is_empty() {
+            return Err(From::from("The exec sink's command is empty"));
+        }
+        Ok(ExecSink {
+            command: exec.command.clone(),
+            batch: exec.batch,
+            input: exec.input,
+            timeout: Duration::from_secs(exec.timeout.unwrap_or(DEFAULT_TIMEOUT)),
+        })
+    }
+    fn _env(&self) -> bool {
+        self.input != ExecInput::Json
+    }
+    fn _json(&self) -> bool {
+        self.input != ExecInput::Env
+    }
+    /// Runs the command once, killing it if it takes too long.
+    fn _run(&self, env: Vec<(String, String)>, stdin: Option<String>) -> Result<(), Box<dyn Error>> {
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .envs(env)
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // both pipes are handled on their own threads, so a chatty or stubborn command can't block us
+        if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            thread::spawn(move || pipe.write_all(input.as_bytes()));
+        }
+        let mut pipe = child.stderr.take().expect("stderr is piped");
+        let stderr = thread::spawn(move || {
+            let mut stderr = String::new();
+            let _ = pipe.read_to_string(&mut stderr);
+            stderr
+        });
+
+        let status = _wait(&mut child, self.timeout)?;
+        match status {
+            Some(status) if status.success() => Ok(()),
+            Some(status) => {
+                let stderr = stderr.join().unwrap_or_default();
+                let stderr = stderr.trim();
+                let how = status.code().map_or_else(|| "was stopped by a signal".to_string(), |code| format!("exited with code {}", code));
+                Err(From::from(format!("{} {}{}{}", self.command[0], how,
+                    if stderr.is_empty() { "" } else { ": " }, truncate(stderr, MAX_STDERR))))
+            },
+            None => Err(From::from(format!("{} was killed after running for longer than {:?}", self.command[0], self.timeout))),
+        }
+    }
+}
+impl Sink for ExecSink {
+    fn notifier(&self) -> Notifier {
+        Notifier::Exec
+    }
+    fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        if self.batch {
+            let count: usize = posts.values().map(|list| list.len()).sum();
+            let env = if self._env() { vec![("REDDIT_COUNT".to_string(), count.to_string())] } else { Vec::new() };
+            let stdin = if self._json() { Some(serde_json::to_string(posts).map_err(|e| Undelivered::all(e, posts))?) } else { None };
+            return self._run(env, stdin).map_err(|e| Undelivered::all(e, posts));
+        }
+
+        // every post gets its turn even if some runs fail; only those are tried again
+        let mut errors = Vec::new();
+        let mut missed = Vec::new();
+        let mut runs = 0;
+        for (sub, list) in posts {
+            for p in list {
+                runs += 1;
+                let env = if self._env() { _env(sub, p) } else { Vec::new() };
+                let result = if self._json() { serde_json::to_string(p).map(Some).map_err(From::from) } else { Ok(None) };
+                if let Err(e) = result.and_then(|stdin| self._run(env, stdin)) {
+                    errors.push(format!("{}: {}", p.fullname, e));
+                    missed.push(Missed { key: sub.clone(), post: p.clone(), to: None });
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Undelivered { error: From::from(format!("{} of {} run(s) failed; {}", errors.len(), runs, errors.join("; "))), posts: missed })
+        }
+    }
+}
+
+/// Waits for the command to finish, or kills it once the timeout is up (handing back `None`).
+fn _wait(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>, Box<dyn Error>> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if started.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// The environment variables describing a post; `sub` is the subreddit, or the thread for thread watches.
+fn _env(sub: &str, p: &Post) -> Vec<(String, String)> {
+    vec![
+        ("REDDIT_SUBREDDIT".to_string(), sub.to_string()),
+        ("REDDIT_FULLNAME".to_string(), p.fullname.clone()),
+        ("REDDIT_TITLE".to_string(), p.title.clone()),
+        ("REDDIT_AUTHOR".to_string(), p.author.clone()),
+        ("REDDIT_LINK".to_string(), p.link.to_string()),
+        ("REDDIT_PERMALINK".to_string(), p.permalink.to_string()),
+        ("REDDIT_DOMAIN".to_string(), p.domain.clone()),
+        ("REDDIT_FLAIR".to_string(), p.flair.clone().unwrap_or_default()),
+        ("REDDIT_NSFW".to_string(), p.nsfw.to_string()),
+        ("REDDIT_SPOILER".to_string(), p.spoiler.to_string()),
+        ("REDDIT_SCORE".to_string(), p.score.to_string()),
+        ("REDDIT_COMMENTS".to_string(), p.comments.to_string()),
+        ("REDDIT_CREATED_UTC".to_string(), p.created_utc.to_string()),
+    ]
+}
+
+#[cfg(all(test, unix))]
+mod exec_tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    fn sink(script: &str, batch: bool, timeout: u64) -> ExecSink {
+        let exec = Exec { command: vec!["sh".to_string(), "-c".to_string(), script.to_string()], batch, input: ExecInput::Both, timeout: Some(timeout) };
+        ExecSink::new(&exec).unwrap()
+    }
+
+    #[test]
+    fn passes_posts_through_env_and_stdin() {
+        let out = env::temp_dir().join(format!("{}-exec-test", env!("CARGO_PKG_NAME")));
+        let _ = fs::remove_file(&out);
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![Post { title: "Tips & tricks".to_string(), ..Post::example("abc") }]);
+
+        let script = format!("echo \"$REDDIT_SUBREDDIT $REDDIT_TITLE\" >> {0}; cat >> {0}; echo >> {0}", out.display());
+        sink(&script, false, 5).send(&posts).unwrap();
+        let script = format!("echo \"$REDDIT_COUNT\" >> {0}; cat >> {0}", out.display());
+        sink(&script, true, 5).send(&posts).unwrap();
+
+        let output = fs::read_to_string(&out).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "r/rust Tips & tricks");
+        let json: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(json["fullname"], "t3_abc");
+        assert_eq!(lines[2], "1");
+        let json: serde_json::Value = serde_json::from_str(lines[3]).unwrap();
+        assert_eq!(json["r/rust"][0]["fullname"], "t3_abc");
+        fs::remove_file(&out).unwrap();
+    }
+    #[test]
+    fn reports_failures_and_timeouts() {
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![Post { title: "Tips & tricks".to_string(), ..Post::example("abc") }]);
+
+        let e = sink("echo oops >&2; exit 3", false, 5).send(&posts).unwrap_err().to_string();
+        assert!(e.starts_with("1 of 1 run(s) failed; t3_abc: sh exited with code 3: oops"), "{}", e);
+
+        let started = Instant::now();
+        let e = sink("sleep 10", true, 1).send(&posts).unwrap_err().to_string();
+        assert!(e.contains("killed"), "{}", e);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,159 @@
+//! Sends new posts to a Matrix room through the client-server API, as HTML messages laid out like
+//! the Discord embeds (with the `text` template as the plain-text fallback).
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::json;
+use url::Url;
+
+use crate::config::{Config, Matrix, Notifier};
+use crate::reddit::Post;
+use crate::sinks::{Missed, Sink, Undelivered};
+use crate::template::TemplateKind;
+use crate::utils::escape_html;
+
+/// Keeps each message comfortably under Matrix's 64KiB event limit.
+const POSTS_PER_MESSAGE: usize = 20;
+
+pub struct MatrixSink {
+    client: Client,
+    homeserver: Url,
+    access_token: String,
+    room: String,
+    config: Arc<Mutex<Config>>,
+    sent: AtomicUsize, // counts messages, so every one gets its own transaction id
+}
+impl MatrixSink {
+    pub fn new(matrix: &Matrix, config: Arc<Mutex<Config>>) -> reqwest::Result<MatrixSink> {
+        Ok(MatrixSink {
+            client: Client::builder().build()?,
+            homeserver: matrix.homeserver.clone(),
+            access_token: matrix.access_token.clone(),
+            room: matrix.room.clone(),
+            config,
+            sent: AtomicUsize::new(0),
+        })
+    }
+    /// The url to send a message to the room with, see
+    /// https://matrix.org/docs/spec/client_server/r0.4.0.html#put-matrix-client-r0-rooms-roomid-send-eventtype-txnid
+    fn _send_url(&self) -> Result<Url, Box<dyn Error>> {
+        let txn = format!("redditing-buddy-{}-{}", Utc::now().timestamp_millis(), self.sent.fetch_add(1, Ordering::SeqCst));
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| "The Matrix homeserver isn't a valid base url")?
+            .pop_if_empty()
+            .extend(&["_matrix", "client", "r0", "rooms", &self.room, "send", "m.room.message", &txn]);
+        Ok(url)
+    }
+}
+impl Sink for MatrixSink {
+    fn notifier(&self) -> Notifier {
+        Notifier::Matrix
+    }
+    fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        // every message gets its turn even if some fail; only the posts in those are tried again
+        let mut missed = Vec::new();
+        let mut errors = Vec::new();
+        for (sub, list) in posts {
+            let text = self.config.lock().expect("Arc lock was poisoned in the config").template(sub, TemplateKind::Text);
+            for chunk in list.chunks(POSTS_PER_MESSAGE) {
+                let body = json!({
+                    "msgtype": "m.text",
+                    "body": chunk.iter().map(|p| text.render(p)).collect::<Vec<String>>().join("\n"),
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": _html(sub, chunk),
+                });
+                let result = self._send_url().and_then(|url| Ok(self.client.put(url)
+                    .bearer_auth(&self.access_token)
+                    .json(&body)
+                    .send()?
+                    .error_for_status()?));
+                if let Err(e) = result {
+                    errors.push(format!("{}: {}", sub, e));
+                    missed.extend(chunk.iter().map(|p| Missed { key: sub.clone(), post: p.clone(), to: None }));
+                }
+            }
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(Undelivered { error: From::from(format!("{} message(s) failed; {}", errors.len(), errors.join("; "))), posts: missed })
+    }
+}
+
+fn _html(sub: &str, posts: &[Post]) -> String {
+    let mut html = format!("<h4><a href=\"https://old.reddit.com/{}\">{}</a></h4>", escape_html(sub), escape_html(sub));
+    for p in posts {
+        let mut tags = String::new();
+        if p.nsfw { tags.push_str("[NSFW] "); }
+        if p.spoiler { tags.push_str("[Spoiler] "); }
+        if let Some(ref flair) = p.flair { tags.push_str(&format!("[{}] ", flair)); }
+
+        html.push_str(&format!(
+            "<p><b>{}{}</b><br>[{:+}] <a href=\"{}\">{} comment{}</a> <a href=\"{}\">link</a><br><i>posted {} ago by u/{}</i></p>",
+            escape_html(&tags), escape_html(&p.title), p.score, escape_html(p.permalink.as_str()), p.comments,
+            if p.comments == 1 { "" } else { "s" }, escape_html(p.link.as_str()),
+            humantime::format_duration(p.posted), escape_html(&p.author),
+        ));
+    }
+    html
+}
+
+#[cfg(test)]
+mod matrix_tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use super::*;
+
+    /// Accepts a single request, answers it like a homeserver would, and hands back what was asked.
+    fn stand_in_homeserver() -> (Url, thread::JoinHandle<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.to_lowercase().starts_with("content-length:") {
+                    length = line[15..].trim().parse().unwrap();
+                }
+                if line == "\r\n" { break; }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let reply = "{\"event_id\":\"$1\"}";
+            write!(&stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", reply.len(), reply).unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn sends_html_messages() {
+        let (homeserver, server) = stand_in_homeserver();
+        let matrix = Matrix { homeserver, access_token: "secret".to_string(), room: "!room:example.org".to_string() };
+        let sink = MatrixSink::new(&matrix, Arc::new(Mutex::new(Config::example(&[])))).unwrap();
+
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![Post { title: "Tips & tricks".to_string(), flair: Some("Help".to_string()), comments: 2, ..Post::example("abc") }]);
+        sink.send(&posts).unwrap();
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("PUT /_matrix/client/r0/rooms/!room:example.org/send/m.room.message/redditing-buddy-"));
+        assert!(head.to_lowercase().contains("authorization: bearer secret"));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["format"], "org.matrix.custom.html");
+        assert_eq!(body["body"], "[Help] Tips & tricks [+3] [2 comments] https://old.reddit.com/r/rust/comments/abc/");
+        assert!(body["formatted_body"].as_str().unwrap().contains("<b>[Help] Tips &amp; tricks</b>"));
+    }
+}
@@ -0,0 +1,213 @@
+//! Sinks are the places besides Discord that new posts can be sent to. Each one is set up from its
+//! own section of the config, and every subreddit can pick which of them (Discord included) its
+//! posts go to with its `notify` list.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{Config, Desktop, Notifier};
+use crate::reddit::Post;
+
+pub mod atom;
+#[cfg(all(unix, not(target_os = "macos")))]
+pub mod desktop;
+pub mod email;
+pub mod exec;
+pub mod matrix;
+pub mod slack;
+pub mod telegram;
+
+pub trait Sink: Send {
+    fn notifier(&self) -> Notifier;
+    /// Sends one check's worth of new posts, keyed by subreddit.
+    fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered>;
+    /// Sends one check's worth of new comments in watched threads, keyed by the thread's permalink.
+    /// Most sinks show them just like posts.
+    fn send_threads(&self, threads: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        self.send(threads)
+    }
+    /// Sends posts again to the one destination they missed, for sinks that have several (see
+    /// `Missed`). The others only ever have the one, so they send them like new ones.
+    fn send_to(&self, _to: &str, posts: &HashMap<String, Vec<Post>>, threads: bool) -> Result<(), Undelivered> {
+        if threads { self.send_threads(posts) } else { self.send(posts) }
+    }
+}
+
+/// Why a sink couldn't send everything it was given, and which posts didn't get through, so that
+/// only those are tried again.
+#[derive(Debug)]
+pub struct Undelivered {
+    pub error: Box<dyn Error>,
+    pub posts: Vec<Missed>,
+}
+impl Undelivered {
+    /// None of the posts got through.
+    pub fn all<E: Into<Box<dyn Error>>>(error: E, posts: &HashMap<String, Vec<Post>>) -> Undelivered {
+        let posts = posts.iter()
+            .flat_map(|(key, list)| list.iter().map(move |p| Missed { key: key.clone(), post: p.clone(), to: None }))
+            .collect();
+        Undelivered { error: error.into(), posts }
+    }
+    /// Every post got through, or was kept by the sink to go out later, but something still failed.
+    pub fn none<E: Into<Box<dyn Error>>>(error: E) -> Undelivered {
+        Undelivered { error: error.into(), posts: Vec::new() }
+    }
+    /// Whether a post (by the key it was sent under and its fullname) didn't get through.
+    pub fn missed(&self, key: &str, fullname: &str) -> bool {
+        self.posts.iter().any(|m| m.key == key && m.post.fullname == fullname)
+    }
+}
+impl fmt::Display for Undelivered {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+/// A post that didn't get through. One that reached some of a sink's destinations (like Telegram's
+/// chats) but not others is listed once for every destination it missed.
+#[derive(Debug)]
+pub struct Missed {
+    pub key: String, // subreddit, or the thread's permalink
+    pub post: Post,
+    pub to: Option<String>, // the destination it missed, for sinks with several
+}
+
+/// Every notifier besides Discord, in the order their sinks are set up.
+const SINKS: [Notifier; 7] = [Notifier::Slack, Notifier::Matrix, Notifier::Desktop, Notifier::Feed, Notifier::Email, Notifier::Telegram, Notifier::Exec];
+
+/// Sets up every sink that has a section in the config.
+pub fn from_config(shared: &Arc<Mutex<Config>>) -> Result<Vec<Box<dyn Sink>>, Box<dyn Error>> {
+    let config = shared.lock().expect("Arc lock was poisoned in the config");
+    _build(&config, &SINKS, shared)
+}
+
+/// Sets up the sinks whose sections changed when the config is reloaded, leaving the rest (and
+/// whatever they keep, like the feeds' entries) as they are. If any of them can't be set up, the
+/// old ones are set up again and the error is handed back.
+pub fn reload(sinks: &mut Vec<Box<dyn Sink>>, running: &Config, fresh: &Config, shared: &Arc<Mutex<Config>>) -> Result<(), Box<dyn Error>> {
+    let changed: Vec<Notifier> = SINKS.iter().cloned().filter(|n| _section(running, *n) != _section(fresh, *n)).collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+    // the old ones go first, so they let go of anything the new ones might need, like an address
+    sinks.retain(|s| !changed.contains(&s.notifier()));
+    match _build(fresh, &changed, shared) {
+        Ok(built) => {
+            sinks.extend(built);
+            Ok(())
+        },
+        Err(e) => {
+            match _build(running, &changed, shared) {
+                Ok(built) => sinks.extend(built),
+                Err(again) => eprintln!("Could not set the old sinks up again either: {}", again),
+            }
+            Err(e)
+        },
+    }
+}
+
+/// Sets up the given notifiers' sinks, for the ones that have a section in the config.
+fn _build(config: &Config, notifiers: &[Notifier], shared: &Arc<Mutex<Config>>) -> Result<Vec<Box<dyn Sink>>, Box<dyn Error>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    for notifier in notifiers {
+        match *notifier {
+            Notifier::Slack => if let Some(ref slack) = config.slack {
+                sinks.push(Box::new(slack::SlackSink::new(slack, Arc::clone(shared))?));
+            },
+            Notifier::Matrix => if let Some(ref matrix) = config.matrix {
+                sinks.push(Box::new(matrix::MatrixSink::new(matrix, Arc::clone(shared))?));
+            },
+            Notifier::Desktop => if let Some(ref desktop) = config.desktop {
+                sinks.push(_desktop(desktop)?);
+            },
+            Notifier::Feed => if let Some(ref feeds) = config.feeds {
+                sinks.push(Box::new(atom::AtomSink::new(feeds, Arc::clone(shared))?));
+            },
+            Notifier::Email => if let Some(ref email) = config.email {
+                sinks.push(Box::new(email::EmailSink::new(email, Arc::clone(shared))));
+            },
+            Notifier::Telegram => if let Some(ref telegram) = config.telegram {
+                sinks.push(Box::new(telegram::TelegramSink::new(telegram, Arc::clone(shared))?));
+            },
+            Notifier::Exec => if let Some(ref exec) = config.exec {
+                sinks.push(Box::new(exec::ExecSink::new(exec)?));
+            },
+            Notifier::Discord => {},
+        }
+    }
+    Ok(sinks)
+}
+
+/// A sink's section of the config, written out so two configs' can be compared.
+fn _section(config: &Config, notifier: Notifier) -> ron::ser::Result<String> {
+    match notifier {
+        Notifier::Slack => ron::ser::to_string(&config.slack),
+        Notifier::Matrix => ron::ser::to_string(&config.matrix),
+        Notifier::Desktop => ron::ser::to_string(&config.desktop),
+        Notifier::Feed => ron::ser::to_string(&config.feeds),
+        Notifier::Email => ron::ser::to_string(&config.email),
+        Notifier::Telegram => ron::ser::to_string(&config.telegram),
+        Notifier::Exec => ron::ser::to_string(&config.exec),
+        Notifier::Discord => Ok(String::new()),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn _desktop(desktop: &Desktop) -> Result<Box<dyn Sink>, Box<dyn Error>> {
+    Ok(Box::new(desktop::DesktopSink::new(desktop)))
+}
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn _desktop(_: &Desktop) -> Result<Box<dyn Sink>, Box<dyn Error>> {
+    Err(From::from("Desktop notifications are only supported where there's a freedesktop notification server, like on Linux"))
+}
+
+/// The posts from subreddits that want to be sent to `notifier`.
+pub fn select(config: &Config, posts: &HashMap<String, Vec<Post>>, notifier: Notifier) -> HashMap<String, Vec<Post>> {
+    posts.iter()
+        .filter(|(sub, _)| config.notifies(sub, notifier))
+        .map(|(sub, list)| (sub.clone(), list.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod sinks_tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// A config with just the exec and feeds sections, given as they'd be written in the file.
+    fn config(exec: &str, feeds: &str) -> Config {
+        let mut config = Config::example(&[]);
+        config.exec = ron::de::from_str(exec).unwrap();
+        config.feeds = ron::de::from_str(feeds).unwrap();
+        config
+    }
+
+    /// An address nothing is listening on, for the feeds' server.
+    fn free_address() -> String {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn reloads_only_set_up_what_changed() {
+        let address = free_address();
+        let running = config(r#"Some((command: ["true"]))"#, &format!(r#"Some((listen: Some("{}")))"#, address));
+        let shared = Arc::new(Mutex::new(Config::example(&[])));
+        let mut sinks = _build(&running, &SINKS, &shared).unwrap();
+
+        // the feeds keep their address, which the old sink has to give up first
+        let fresh = config(r#"Some((command: ["true"]))"#, &format!(r#"Some((listen: Some("{}"), entries: Some(10)))"#, address));
+        reload(&mut sinks, &running, &fresh, &shared).unwrap();
+        assert_eq!(sinks.iter().map(|s| s.notifier()).collect::<Vec<Notifier>>(), vec![Notifier::Exec, Notifier::Feed]);
+
+        // a sink that can't be set up keeps the old one around
+        let broken = config(r#"Some((command: []))"#, &format!(r#"Some((listen: Some("{}"), entries: Some(10)))"#, address));
+        assert!(reload(&mut sinks, &fresh, &broken, &shared).is_err());
+        assert_eq!(sinks.iter().map(|s| s.notifier()).collect::<Vec<Notifier>>(), vec![Notifier::Feed, Notifier::Exec]);
+
+        reload(&mut sinks, &fresh, &Config::example(&[]), &shared).unwrap();
+        assert!(sinks.is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+//! Posts new posts to a Slack channel through an incoming webhook, as Block Kit messages with a
+//! section per post, rendered from the `slack` template.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use crate::config::{Config, Notifier, Slack};
+use crate::reddit::Post;
+use crate::sinks::{Missed, Sink, Undelivered};
+use crate::template::{Template, TemplateKind};
+
+/// Slack allows 50 blocks per message; each post takes one, and the subreddit header one more.
+const POSTS_PER_MESSAGE: usize = 49;
+
+pub struct SlackSink {
+    client: Client,
+    webhook: String,
+    config: Arc<Mutex<Config>>,
+}
+impl SlackSink {
+    pub fn new(slack: &Slack, config: Arc<Mutex<Config>>) -> reqwest::Result<SlackSink> {
+        Ok(SlackSink { client: Client::builder().build()?, webhook: slack.webhook.to_string(), config })
+    }
+}
+impl Sink for SlackSink {
+    fn notifier(&self) -> Notifier {
+        Notifier::Slack
+    }
+    fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        // every message gets its turn even if some fail; only the posts in those are tried again
+        let mut missed = Vec::new();
+        let mut errors = Vec::new();
+        for (sub, list) in posts {
+            let template = self.config.lock().expect("Arc lock was poisoned in the config").template(sub, TemplateKind::Slack);
+            for chunk in list.chunks(POSTS_PER_MESSAGE) {
+                let result = self.client.post(&self.webhook)
+                    .json(&_message(sub, chunk, &template))
+                    .send()
+                    .and_then(|r| r.error_for_status());
+                if let Err(e) = result {
+                    errors.push(format!("{}: {}", sub, e));
+                    missed.extend(chunk.iter().map(|p| Missed { key: sub.clone(), post: p.clone(), to: None }));
+                }
+            }
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(Undelivered { error: From::from(format!("{} message(s) failed; {}", errors.len(), errors.join("; "))), posts: missed })
+    }
+}
+
+/// Escapes the characters Slack treats as markup in `mrkdwn` text.
+fn _escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn _message(sub: &str, posts: &[Post], template: &Template) -> Value {
+    let mut blocks = vec![json!({
+        "type": "section",
+        "text": { "type": "mrkdwn", "text": format!("*<https://old.reddit.com/{}|{}>*", sub, _escape(sub)) },
+    })];
+    for p in posts {
+        blocks.push(json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": template.render_escaped(p, _escape) },
+        }));
+    }
+
+    json!({
+        "text": format!("{} new post(s) in {}", posts.len(), sub), // shown in notifications
+        "blocks": blocks,
+    })
+}
+
+#[cfg(test)]
+mod slack_tests {
+    use super::*;
+
+    fn post(n: usize) -> Post {
+        Post { title: "Tips & <tricks>".to_string(), ..Post::example(&n.to_string()) }
+    }
+
+    #[test]
+    fn renders_blocks() {
+        let message = _message("r/rust", &[post(1)], &Template::default_for(TemplateKind::Slack));
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1]["text"]["text"], "*<https://old.reddit.com/r/rust/comments/1/|Tips &amp; &lt;tricks&gt;>*\n\
+            +3 · 1 comment · posted 1m ago by u/ferris");
+    }
+    #[test]
+    fn stays_within_block_limit() {
+        let posts: Vec<Post> = (0..POSTS_PER_MESSAGE).map(post).collect();
+        assert!(_message("r/rust", &posts, &Template::default_for(TemplateKind::Slack))["blocks"].as_array().unwrap().len() <= 50);
+    }
+}
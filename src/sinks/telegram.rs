@@ -0,0 +1,159 @@
+//! Sends every new post to a set of Telegram chats through the Bot API, one MarkdownV2 message each,
+//! rendered from the `telegram` template. The default one puts the post's link first so Telegram
+//! previews it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use reqwest::Client;
+use serde_json::json;
+
+use crate::config::{Config, Notifier, Telegram};
+use crate::reddit::Post;
+use crate::sinks::{Missed, Sink, Undelivered};
+use crate::template::{Template, TemplateKind};
+use crate::utils::truncate;
+
+/// Telegram's limit on the length of a message's text.
+const MAX_MESSAGE: usize = 4096;
+
+pub struct TelegramSink {
+    client: Client,
+    url: String,
+    chat_ids: Vec<i64>,
+    config: Arc<Mutex<Config>>,
+}
+impl TelegramSink {
+    pub fn new(telegram: &Telegram, config: Arc<Mutex<Config>>) -> reqwest::Result<TelegramSink> {
+        Ok(TelegramSink {
+            client: Client::builder().build()?,
+            url: format!("https://api.telegram.org/bot{}/sendMessage", telegram.bot_token),
+            chat_ids: telegram.chat_ids.clone(),
+            config,
+        })
+    }
+    fn _post(&self, chat_id: i64, text: &str) -> reqwest::Result<()> {
+        self.client.post(&self.url)
+            .json(&json!({ "chat_id": chat_id, "text": text, "parse_mode": "MarkdownV2" }))
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+    /// Sends every post to each of the chats, even if some messages fail. Failures are counted per
+    /// chat, keeping the first error of each, and every post is noted for each chat it missed.
+    fn _send(&self, posts: &HashMap<String, Vec<Post>>, chat_ids: &[i64]) -> Result<(), Undelivered> {
+        let mut failed: BTreeMap<i64, (usize, reqwest::Error)> = BTreeMap::new();
+        let mut missed = Vec::new();
+        let mut messages = 0;
+        for (sub, list) in posts {
+            let template = self.config.lock().expect("Arc lock was poisoned in the config").template(sub, TemplateKind::Telegram);
+            for post in list {
+                let text = _message(post, &template);
+                for chat_id in chat_ids {
+                    messages += 1;
+                    if let Err(e) = self._post(*chat_id, &text) {
+                        failed.entry(*chat_id).or_insert((0, e)).0 += 1;
+                        missed.push(Missed { key: sub.clone(), post: post.clone(), to: Some(chat_id.to_string()) });
+                    }
+                }
+            }
+        }
+        if failed.is_empty() {
+            return Ok(());
+        }
+        let count: usize = failed.values().map(|(n, _)| n).sum();
+        let chats: Vec<String> = failed.iter().map(|(chat_id, (n, e))| format!("chat {}: {} failed, first with {}", chat_id, n, e)).collect();
+        Err(Undelivered { error: From::from(format!("{} of {} message(s) failed; {}", count, messages, chats.join("; "))), posts: missed })
+    }
+}
+impl Sink for TelegramSink {
+    fn notifier(&self) -> Notifier {
+        Notifier::Telegram
+    }
+    fn send(&self, posts: &HashMap<String, Vec<Post>>) -> Result<(), Undelivered> {
+        self._send(posts, &self.chat_ids)
+    }
+    /// Sends posts again to the one chat (by id) that they missed.
+    fn send_to(&self, to: &str, posts: &HashMap<String, Vec<Post>>, _threads: bool) -> Result<(), Undelivered> {
+        match to.parse() {
+            Ok(chat_id) => self._send(posts, &[chat_id]),
+            Err(e) => Err(Undelivered::none(format!("\"{}\" isn't a chat id: {}", to, e))),
+        }
+    }
+}
+
+/// Escapes text for MarkdownV2, see https://core.telegram.org/bots/api#markdownv2-style; escaping
+/// more than needed is allowed, so this works for the urls inside links too.
+fn _escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn _message(p: &Post, template: &Template) -> String {
+    let mut text = template.render_escaped(p, _escape);
+    if !p.selftext.is_empty() {
+        // escaping at most doubles the length, so half of what's left is always enough room
+        let room = MAX_MESSAGE.saturating_sub(text.chars().count() + 2) / 2;
+        if room > 0 {
+            text.push_str("\n\n");
+            text.push_str(&_escape(&truncate(&p.selftext, room)));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod telegram_tests {
+    use url::Url;
+
+    use super::*;
+
+    fn post() -> Post {
+        Post {
+            title: "Rust 1.35 is out!".to_string(),
+            author: "ferris_the_crab".to_string(),
+            link: Url::parse("https://blog.rust-lang.org/2019/05/23/Rust-1.35.0.html").unwrap(),
+            domain: "blog.rust-lang.org".to_string(),
+            ..Post::example("abc")
+        }
+    }
+
+    #[test]
+    fn escapes_markdown() {
+        let template = Template::default_for(TemplateKind::Telegram);
+        assert_eq!(_message(&post(), &template), "[blog\\.rust\\-lang\\.org](https://blog\\.rust\\-lang\\.org/2019/05/23/Rust\\-1\\.35\\.0\\.html) · \
+            [comments](https://old\\.reddit\\.com/r/rust/comments/abc/)\n*Rust 1\\.35 is out\\!*\n\\+3 · 1 comment · posted 1m ago by u/ferris\\_the\\_crab");
+    }
+    #[test]
+    fn failures_are_counted_per_chat() {
+        let sink = TelegramSink {
+            client: Client::new(),
+            url: "http://127.0.0.1:1/sendMessage".to_string(), // nothing listens there
+            chat_ids: vec![1, 2],
+            config: Arc::new(Mutex::new(Config::example(&[]))),
+        };
+        let posts = vec![("r/rust".to_string(), vec![post(), post()])].into_iter().collect();
+        let undelivered = sink.send(&posts).unwrap_err();
+        let e = undelivered.to_string();
+        assert!(e.starts_with("4 of 4 message(s) failed; chat 1: 2 failed, first with "), "{}", e);
+        assert!(e.contains("; chat 2: 2 failed, first with "), "{}", e);
+        let chats: Vec<Option<&str>> = undelivered.posts.iter().map(|m| m.to.as_ref().map(String::as_str)).collect();
+        assert_eq!(chats, vec![Some("1"), Some("2"), Some("1"), Some("2")]);
+
+        // trying again only goes to the chat that was missed
+        let e = sink.send_to("2", &posts, false).unwrap_err().to_string();
+        assert!(e.starts_with("2 of 2 message(s) failed; chat 2: 2 failed"), "{}", e);
+    }
+    #[test]
+    fn stays_within_length_limit() {
+        let mut long = post();
+        long.selftext = ".".repeat(10000);
+        assert!(_message(&long, &Template::default_for(TemplateKind::Telegram)).chars().count() <= MAX_MESSAGE);
+    }
+}
@@ -0,0 +1,45 @@
+//! Everything the app needs to remember between checks (and restarts) that doesn't belong in the
+//! user-edited config lives here, and is saved next to the config file.
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use ron;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::digest::Digests;
+use crate::discord::SentMessages;
+use crate::feedback::Feedback;
+use crate::outbox::Outbox;
+use crate::threads::Threads;
+use crate::tracker::Tracker;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct State {
+    pub tracker: Tracker,
+    pub messages: SentMessages,
+    pub outbox: Outbox,
+    pub feedback: Feedback,
+    pub digests: Digests,
+    pub threads: Threads,
+}
+impl State {
+    /// The state file for a given config, e.g. `config.ron` keeps its state in `config.state.ron`.
+    pub fn path_for(config_path: &PathBuf) -> PathBuf {
+        config_path.with_extension("state.ron")
+    }
+    pub fn read(file_path: &PathBuf) -> ron::de::Result<State> {
+        if !file_path.exists() {
+            return Ok(State::default());
+        }
+        let input = fs::read_to_string(file_path)?;
+        ron::de::from_str(input.as_str())
+    }
+    pub fn write(&self, file_path: &PathBuf) -> std::result::Result<(), Box<dyn Error>> {
+        fs::write(file_path, ron::ser::to_string_pretty(self, PrettyConfig::default())?.as_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! Notifications are rendered from user-configurable templates, where `{placeholders}` get swapped
+//! out for the matching field of a post. Literal braces are written as `{{` and `}}`.
+//!
+//! Every field of `Post` has a placeholder with the same name, plus a few conveniences:
+//! `{comments_s}` is an "s" unless there's exactly one comment, `{created}` is `created_utc` as a
+//! readable date, and `{tags}` is the post's NSFW/spoiler/flair tags (e.g. "[NSFW] [Help] ").
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use chrono::{TimeZone, Utc};
+
+use crate::reddit::Post;
+
+#[derive(Debug)]
+pub struct TemplateError(String);
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Error for TemplateError {}
+
+/// The different places a template can be used in; each has its own default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemplateKind {
+    DiscordName, // the name of a post's field in a Discord embed
+    DiscordValue, // the body of a post's field in a Discord embed
+    Text,
+    Slack, // in Slack's mrkdwn
+    Telegram, // in Telegram's MarkdownV2
+}
+impl TemplateKind {
+    pub fn default_template(self) -> &'static str {
+        match self {
+            TemplateKind::DiscordName => "{tags}{title}",
+            TemplateKind::DiscordValue => "[{score}] [{comments} comment{comments_s}]({permalink}) [link]({link})\n*posted {posted} ago by u/{author}*",
+            TemplateKind::Text => "{tags}{title} [{score}] [{comments} comment{comments_s}] {permalink}",
+            TemplateKind::Slack => "*<{permalink}|{tags}{title}>*\n{score} · {comments} comment{comments_s} · posted {posted} ago by u/{author}",
+            TemplateKind::Telegram => "[{domain}]({link}) · [comments]({permalink})\n*{tags}{title}*\n{score} · {comments} comment{comments_s} · posted {posted} ago by u/{author}",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Fullname, Title, Author, Subreddit, Link, Permalink, Domain, Flair, Nsfw, Spoiler, Thumbnail,
+    Image, Selftext, Score, Comments, CommentsS, CreatedUtc, Created, Posted, Removal, Tags,
+}
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        Some(match name {
+            "fullname" => Field::Fullname,
+            "title" => Field::Title,
+            "author" => Field::Author,
+            "subreddit" => Field::Subreddit,
+            "link" => Field::Link,
+            "permalink" => Field::Permalink,
+            "domain" => Field::Domain,
+            "flair" => Field::Flair,
+            "nsfw" => Field::Nsfw,
+            "spoiler" => Field::Spoiler,
+            "thumbnail" => Field::Thumbnail,
+            "image" => Field::Image,
+            "selftext" => Field::Selftext,
+            "score" => Field::Score,
+            "comments" => Field::Comments,
+            "comments_s" => Field::CommentsS,
+            "created_utc" => Field::CreatedUtc,
+            "created" => Field::Created,
+            "posted" => Field::Posted,
+            "removal" => Field::Removal,
+            "tags" => Field::Tags,
+            _ => return None,
+        })
+    }
+    fn value(self, p: &Post) -> String {
+        match self {
+            Field::Fullname => p.fullname.clone(),
+            Field::Title => p.title.clone(),
+            Field::Author => p.author.clone(),
+            Field::Subreddit => p.subreddit.clone(),
+            Field::Link => p.link.to_string(),
+            Field::Permalink => p.permalink.to_string(),
+            Field::Domain => p.domain.clone(),
+            Field::Flair => p.flair.clone().unwrap_or_default(),
+            Field::Nsfw => p.nsfw.to_string(),
+            Field::Spoiler => p.spoiler.to_string(),
+            Field::Thumbnail => p.thumbnail.as_ref().map(|u| u.to_string()).unwrap_or_default(),
+            Field::Image => p.image.as_ref().map(|u| u.to_string()).unwrap_or_default(),
+            Field::Selftext => p.selftext.clone(),
+            Field::Score => format!("{:+}", p.score),
+            Field::Comments => p.comments.to_string(),
+            Field::CommentsS => if p.comments == 1 { String::new() } else { "s".to_string() },
+            Field::CreatedUtc => p.created_utc.to_string(),
+            Field::Created => Utc.timestamp_opt(p.created_utc, 0).single().map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string()).unwrap_or_default(),
+            Field::Posted => humantime::format_duration(p.posted).to_string(),
+            Field::Removal => p.removal.as_ref().map(|r| r.to_string()).unwrap_or_default(),
+            Field::Tags => {
+                let mut tags = String::new();
+                if p.nsfw { tags.push_str("[NSFW] "); }
+                if p.spoiler { tags.push_str("[Spoiler] "); }
+                if let Some(ref flair) = p.flair { tags.push_str(&format!("[{}] ", flair)); }
+                tags
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone)]
+pub struct Template(Vec<Part>);
+impl Template {
+    pub fn parse(s: &str) -> Result<Template, TemplateError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => { chars.next(); literal.push('{'); },
+                '}' if chars.peek() == Some(&'}') => { chars.next(); literal.push('}'); },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError(format!("Unclosed {{ in template \"{}\"", s))),
+                        }
+                    }
+                    let field = Field::from_name(name.trim())
+                        .ok_or_else(|| TemplateError(format!("Unknown placeholder {{{}}} in template \"{}\"", name, s)))?;
+                    if !literal.is_empty() { parts.push(Part::Literal(literal.split_off(0))); }
+                    parts.push(Part::Field(field));
+                },
+                '}' => return Err(TemplateError(format!("Unmatched }} in template \"{}\"", s))),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() { parts.push(Part::Literal(literal)); }
+
+        Ok(Template(parts))
+    }
+    /// The built-in template for a kind; these are known to be valid.
+    pub fn default_for(kind: TemplateKind) -> Template {
+        Template::parse(kind.default_template()).expect("Default templates should always parse")
+    }
+    pub fn render(&self, post: &Post) -> String {
+        self.render_escaped(post, |s| s.to_string())
+    }
+    /// Renders the template, passing every substituted value (but not the template's own text)
+    /// through `escape` first, for sinks that need their markup escaped.
+    pub fn render_escaped<F: Fn(&str) -> String>(&self, post: &Post, escape: F) -> String {
+        self.0.iter().map(|part| match part {
+            Part::Literal(s) => s.clone(),
+            Part::Field(f) => escape(&f.value(post)),
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod template_tests {
+    use super::*;
+
+    fn post() -> Post {
+        Post { title: "A *title*".to_string(), flair: Some("Help".to_string()), nsfw: true, score: 5, ..Post::example("abc") }
+    }
+
+    #[test]
+    fn renders_placeholders_and_escaped_braces() {
+        let t = Template::parse("{{{score}}} {tags}{title} by {author}, {comments} comment{comments_s}").unwrap();
+        assert_eq!(t.render(&post()), "{+5} [NSFW] [Help] A *title* by ferris, 1 comment");
+    }
+    #[test]
+    fn escapes_only_substituted_values() {
+        let t = Template::parse("*{title}*").unwrap();
+        assert_eq!(t.render_escaped(&post(), |s| s.replace('*', "\\*")), "*A \\*title\\**");
+    }
+    #[test]
+    fn rejects_bad_templates() {
+        assert!(Template::parse("{nope}").is_err());
+        assert!(Template::parse("{title").is_err());
+        assert!(Template::parse("title}").is_err());
+    }
+    #[test]
+    fn defaults_parse() {
+        for kind in &[TemplateKind::DiscordName, TemplateKind::DiscordValue, TemplateKind::Text, TemplateKind::Slack, TemplateKind::Telegram] {
+            assert!(Template::parse(kind.default_template()).is_ok());
+        }
+        assert_eq!(Template::default_for(TemplateKind::Text).render(&post()),
+                   "[NSFW] [Help] A *title* [+5] [1 comment] https://old.reddit.com/r/rust/comments/abc/");
+    }
+}
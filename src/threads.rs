@@ -0,0 +1,137 @@
+//! How far each thread watch has got: when it was first checked, and which comments have been seen
+//! since. This grows with the thread, so it's kept in the saved state rather than the config.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ThreadWatch;
+use crate::reddit::{Post, ThreadComments};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct Progress {
+    pub started: i64, // utc timestamp of the first check, 0 until then
+    pub seen: HashSet<String>, // fullnames of the comments that have already been reported
+    pub expired: bool, // kept until the watch leaves the config file, so a reload doesn't restart it
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Threads(HashMap<String, Progress>); // thread id -> progress
+impl Threads {
+    pub fn get(&self, thread: &str) -> Option<&Progress> {
+        self.0.get(thread)
+    }
+    /// Takes over the progress that configs saved by older versions kept in their thread watches,
+    /// unless the thread already has some.
+    pub fn adopt(&mut self, watches: &mut [ThreadWatch]) {
+        for watch in watches.iter_mut().filter(|w| w.started != 0) {
+            let progress = Progress { started: watch.started, seen: std::mem::take(&mut watch.seen), expired: false };
+            self.0.entry(watch.thread.clone()).or_insert(progress);
+            watch.started = 0;
+        }
+    }
+    /// Drops the watches that have run for as long as they were asked to. Their progress is only
+    /// marked as expired, so that watches read again from the file (on a reload) are dropped too.
+    pub fn expire(&mut self, watches: &mut Vec<ThreadWatch>, now: i64) {
+        let progress = &mut self.0;
+        watches.retain(|w| match progress.get_mut(&w.thread) {
+            Some(ref mut p) if p.started != 0 && now - p.started >= w.expire_after as i64 => {
+                if !p.expired {
+                    println!("The watch on thread {} has expired.", w.thread);
+                    p.expired = true;
+                    p.seen.clear();
+                }
+                false
+            },
+            _ => true,
+        });
+    }
+    /// Forgets the progress of threads that the config file doesn't list anymore.
+    pub fn retain(&mut self, watches: &[ThreadWatch]) {
+        self.0.retain(|thread, _| watches.iter().any(|w| &w.thread == thread));
+    }
+    /// Notes the comments found in each thread as seen, handing back the new ones that passed their
+    /// watch's filters, keyed by the thread's permalink. The first check of a watch only records the
+    /// comments already there.
+    pub fn record(&mut self, checked: HashMap<String, ThreadComments>, now: i64) -> HashMap<String, Vec<Post>> {
+        let mut results = HashMap::new();
+        for (thread, found) in checked {
+            let progress = self.0.entry(thread).or_default();
+            let first_check = progress.started == 0;
+            if first_check { progress.started = now; }
+            let seen = &mut progress.seen;
+
+            let new_comments: Vec<Post> = found.comments.into_iter()
+                .filter(|(c, _)| seen.insert(c.fullname.clone()) && !first_check)
+                .filter(|(_, wanted)| *wanted)
+                .map(|(c, _)| c)
+                .collect();
+            if !new_comments.is_empty() {
+                results.insert(found.permalink, new_comments);
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod threads_tests {
+    use super::*;
+
+    fn watch(thread: &str, expire_after: u64) -> ThreadWatch {
+        ThreadWatch { thread: thread.to_string(), filter: String::new(), top_level_only: false, expire_after, started: 0, seen: HashSet::new() }
+    }
+    fn found(ids: &[(&str, bool)]) -> HashMap<String, ThreadComments> {
+        let comments = ids.iter().map(|(id, wanted)| (Post::example(id), *wanted)).collect();
+        vec![("abc".to_string(), ThreadComments { permalink: "r/rust/comments/abc".to_string(), comments })].into_iter().collect()
+    }
+
+    #[test]
+    fn only_new_wanted_comments_are_reported() {
+        let mut threads = Threads::default();
+        assert!(threads.record(found(&[("a", true)]), 100).is_empty());
+        assert_eq!(threads.get("abc").unwrap().started, 100);
+
+        let new = threads.record(found(&[("a", true), ("b", true), ("c", false)]), 200);
+        let ids: Vec<&str> = new["r/rust/comments/abc"].iter().map(|p| p.fullname.as_str()).collect();
+        assert_eq!(ids, vec!["t3_b"]);
+        assert_eq!(threads.get("abc").unwrap().started, 100);
+        assert!(threads.record(found(&[("a", true), ("b", true), ("c", false)]), 300).is_empty());
+    }
+
+    #[test]
+    fn expired_watches_are_dropped() {
+        let mut threads = Threads::default();
+        threads.record(found(&[("a", true)]), 100);
+        let mut watches = vec![watch("abc", 50), watch("def", 50)];
+        threads.expire(&mut watches, 149);
+        assert_eq!(watches.len(), 2);
+        threads.expire(&mut watches, 150);
+        assert_eq!(watches.iter().map(|w| w.thread.as_str()).collect::<Vec<_>>(), vec!["def"]);
+        assert!(threads.get("abc").unwrap().seen.is_empty());
+
+        // a reload reads the expired watch from the file again
+        let listed = vec![watch("abc", 50), watch("def", 50)];
+        let mut reloaded = listed.clone();
+        threads.expire(&mut reloaded, 151);
+        assert_eq!(reloaded.len(), 1);
+        threads.retain(&listed);
+        assert!(threads.get("abc").is_some());
+
+        // and once it's gone from the file, so is its progress
+        threads.retain(&reloaded);
+        assert!(threads.get("abc").is_none());
+    }
+
+    #[test]
+    fn old_configs_hand_over_their_progress() {
+        let mut threads = Threads::default();
+        let mut watches = vec![watch("abc", 50)];
+        watches[0].started = 100;
+        watches[0].seen.insert("t1_a".to_string());
+        threads.adopt(&mut watches);
+        assert_eq!(threads.get("abc").unwrap().seen.len(), 1);
+        assert_eq!((watches[0].started, watches[0].seen.len()), (0, 0));
+    }
+}
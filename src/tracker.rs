@@ -0,0 +1,190 @@
+//! Posts that have already been sent keep being followed for a while, so that the app can report
+//! when they take off (i.e. cross one of the configured score or comment milestones), or when they
+//! get removed by the mods or deleted by their author.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Tracking;
+use crate::reddit::{Post, Removal};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackedPost {
+    pub subreddit: String,
+    pub post: Post,
+    pub since: i64, // utc timestamp of when the post was first sent
+    pub score_milestone: Option<i64>, // highest milestone reached so far
+    pub comment_milestone: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateKind {
+    Score(i64),
+    Comments(u64),
+    Removed(Removal),
+}
+impl Display for UpdateKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            UpdateKind::Score(s) => write!(f, "reached a score of {:+}", s),
+            UpdateKind::Comments(c) => write!(f, "reached {} comment{}", c, if *c == 1 { "" } else { "s" }),
+            UpdateKind::Removed(r) => write!(f, "{}", r),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Update {
+    pub subreddit: String,
+    pub post: Post,
+    pub kind: UpdateKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Tracker(HashMap<String, TrackedPost>);
+impl Tracker {
+    /// Starts following freshly sent posts. Milestones they had already passed when they were found
+    /// are considered reached, so only future progress gets reported.
+    pub fn track(&mut self, posts: &HashMap<String, Vec<Post>>, tracking: &Tracking) {
+        let now = Utc::now().timestamp();
+        posts.iter().for_each(|(sub, list)| list.iter().for_each(|p| {
+            self.0.entry(p.fullname.clone()).or_insert_with(|| TrackedPost {
+                subreddit: sub.clone(),
+                post: p.clone(),
+                since: now,
+                score_milestone: highest_reached(&tracking.score_milestones, p.score),
+                comment_milestone: highest_reached(&tracking.comment_milestones, p.comments),
+            });
+        }));
+    }
+    /// Stops following posts that were sent longer ago than the tracking window.
+    pub fn expire(&mut self, tracking: &Tracking) {
+        let now = Utc::now().timestamp();
+        self.0.retain(|_, t| now - t.since < tracking.window as i64);
+    }
+    pub fn fullnames(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
+    pub fn get(&self, fullname: &str) -> Option<&TrackedPost> {
+        self.0.get(fullname)
+    }
+    /// Fullnames of the tracked posts whose score, comment count or removal differs in `fresh`.
+    pub fn changed(&self, fresh: &[Post]) -> Vec<String> {
+        fresh.iter()
+            .filter(|p| self.0.get(&p.fullname).map_or(false, |t| t.post.score != p.score || t.post.comments != p.comments || t.post.removal != p.removal))
+            .map(|p| p.fullname.clone())
+            .collect()
+    }
+    /// Takes the latest versions of tracked posts and returns every milestone that was crossed since
+    /// the last refresh (only the highest of each kind is reported per post), as well as any newly
+    /// removed posts if the tracking settings ask for them.
+    pub fn refresh(&mut self, fresh: Vec<Post>, tracking: &Tracking) -> Vec<Update> {
+        let mut updates = Vec::new();
+        for post in fresh {
+            let tracked = match self.0.get_mut(&post.fullname) {
+                Some(t) => t,
+                None => continue,
+            };
+
+            let mut kinds = Vec::new();
+            if tracking.notify_removals && tracked.post.removal.is_none() {
+                if let Some(ref removal) = post.removal {
+                    kinds.push(UpdateKind::Removed(removal.clone()));
+                }
+            }
+
+            // the age is relative to when the post was found, so keep the original one (and the
+            // priority, which comes from the subreddit's rules rather than Reddit)
+            let (posted, priority) = (tracked.post.posted, tracked.post.priority);
+            tracked.post = Post { posted, priority, ..post };
+
+            let score = highest_reached(&tracking.score_milestones, tracked.post.score);
+            if score > tracked.score_milestone {
+                tracked.score_milestone = score;
+                kinds.push(UpdateKind::Score(score.unwrap()));
+            }
+            let comments = highest_reached(&tracking.comment_milestones, tracked.post.comments);
+            if comments > tracked.comment_milestone {
+                tracked.comment_milestone = comments;
+                kinds.push(UpdateKind::Comments(comments.unwrap()));
+            }
+
+            updates.extend(kinds.into_iter().map(|kind| Update {
+                subreddit: tracked.subreddit.clone(),
+                post: tracked.post.clone(),
+                kind,
+            }));
+        }
+
+        updates
+    }
+}
+
+fn highest_reached<T: Ord + Copy>(milestones: &[T], value: T) -> Option<T> {
+    milestones.iter().filter(|&&m| m <= value).max().cloned()
+}
+
+#[cfg(test)]
+mod tracker_tests {
+    use super::*;
+
+    fn post(score: i64, comments: u64) -> Post {
+        Post { score, comments, ..Post::example("abc") }
+    }
+    fn tracking() -> Tracking {
+        Tracking { window: 86400, score_milestones: vec![10, 100], comment_milestones: vec![5], edit_messages: false, notify_removals: true }
+    }
+
+    #[test]
+    fn milestones_already_passed_are_not_reported() {
+        let mut tracker = Tracker::default();
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![post(50, 6)]);
+        tracker.track(&posts, &tracking());
+
+        assert!(tracker.refresh(vec![post(60, 7)], &tracking()).is_empty());
+    }
+    #[test]
+    fn only_the_highest_crossed_milestone_is_reported_once() {
+        let mut tracker = Tracker::default();
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![post(1, 0)]);
+        tracker.track(&posts, &tracking());
+
+        let updates = tracker.refresh(vec![post(150, 5)], &tracking());
+        let kinds: Vec<UpdateKind> = updates.iter().map(|u| u.kind.clone()).collect();
+        assert_eq!(kinds, vec![UpdateKind::Score(100), UpdateKind::Comments(5)]);
+        assert_eq!(updates[0].post.score, 150);
+
+        assert!(tracker.refresh(vec![post(160, 9)], &tracking()).is_empty());
+    }
+    #[test]
+    fn changes_are_detected_against_the_last_refresh() {
+        let mut tracker = Tracker::default();
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![post(1, 0)]);
+        tracker.track(&posts, &tracking());
+
+        assert!(tracker.changed(&[post(1, 0)]).is_empty());
+        assert_eq!(tracker.changed(&[post(2, 0)]), vec!["t3_abc".to_string()]);
+        tracker.refresh(vec![post(2, 0)], &tracking());
+        assert!(tracker.changed(&[post(2, 0)]).is_empty());
+    }
+    #[test]
+    fn removals_are_reported_once() {
+        let mut tracker = Tracker::default();
+        let mut posts = HashMap::new();
+        posts.insert("r/rust".to_string(), vec![post(1, 0)]);
+        tracker.track(&posts, &tracking());
+
+        let removed = Post { removal: Some(Removal::Deleted), ..post(1, 0) };
+        assert_eq!(tracker.changed(&[removed.clone()]), vec!["t3_abc".to_string()]);
+        let updates = tracker.refresh(vec![removed.clone()], &tracking());
+        assert_eq!(updates.iter().map(|u| u.kind.clone()).collect::<Vec<_>>(), vec![UpdateKind::Removed(Removal::Deleted)]);
+        assert!(tracker.refresh(vec![removed], &tracking()).is_empty());
+    }
+}
@@ -56,6 +56,30 @@ pub fn file_exists(file: &OsStr) -> Result<(), OsString> {
     }
 }
 
+/// Shortens `s` to at most `max` characters (not bytes), marking the cut with an ellipsis.
+pub fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        let mut t: String = s.chars().take(max.saturating_sub(1)).collect();
+        t.push('…');
+        t
+    }
+}
+
+/// Escapes text for use in HTML, including inside attribute values.
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Whether `name` could be a subreddit's (without the r/ prefix): 2 to 21 letters, digits or underscores.
+pub fn is_subreddit_name(name: &str) -> bool {
+    lazy_static! {
+        static ref SUBREDDIT_RE: Regex = Regex::new(r"^[A-Za-z0-9_]{2,21}$").unwrap();
+    }
+    SUBREDDIT_RE.is_match(name)
+}
+
 #[cfg(test)]
 mod parse_tests {
     use std::time::Duration;
@@ -125,3 +149,20 @@ mod parse_tests {
             .for_each(|(x, y)| assert_eq!(parse_duration(x).unwrap(), *y));
     }
 }
+
+#[cfg(test)]
+mod truncate_tests {
+    use super::*;
+
+    #[test]
+    fn short_strings_are_untouched() {
+        assert_eq!(truncate("hello", 5), "hello");
+        assert_eq!(truncate("", 0), "");
+    }
+    #[test]
+    fn long_strings_are_cut_on_char_boundaries() {
+        assert_eq!(truncate("hello world", 6), "hello…");
+        assert_eq!(truncate("μμμμμμ", 4), "μμμ…");
+        assert_eq!(truncate("μμμμμμ", 4).chars().count(), 4);
+    }
+}